@@ -0,0 +1,654 @@
+use std::{error::Error, fmt::Display, sync::Arc};
+
+use crate::{
+    ir::{
+        optimize::{self, OptLevel},
+        Closure, IRCompiler, IR,
+    },
+    parser::{Argument, Atom, Expression, Path, Program, Statement},
+    position::{Located, Position},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileError {
+    /// `...expr` outside of a list literal or call arguments
+    MisplacedSpread,
+    BreakOutsideLoop,
+    ContinueOutsideLoop,
+    /// Reading a local of an enclosing function, which closures cannot capture
+    CapturedLocal(String),
+    Cancelled,
+}
+impl Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MisplacedSpread => write!(f, "`...` is only allowed in lists and arguments"),
+            Self::BreakOutsideLoop => write!(f, "`break` outside of a loop"),
+            Self::ContinueOutsideLoop => write!(f, "`continue` outside of a loop"),
+            Self::CapturedLocal(name) => write!(
+                f,
+                "cannot use `{name}` here, it is a local of an enclosing function"
+            ),
+            Self::Cancelled => write!(f, "compilation was cancelled"),
+        }
+    }
+}
+impl Error for CompileError {}
+pub trait Compilable {
+    type Output;
+    fn compile(
+        &self,
+        pos: &Position,
+        compiler: &mut IRCompiler,
+    ) -> Result<Self::Output, Located<CompileError>>;
+}
+impl<T: Compilable> Located<T> {
+    pub fn compile(&self, compiler: &mut IRCompiler) -> Result<T::Output, Located<CompileError>> {
+        self.value.compile(&self.pos, compiler)
+    }
+}
+
+/// Compiles `program` into its main closure, which ends with an implicit `return;`
+pub fn compile(
+    program: &Located<Program>,
+    level: OptLevel,
+) -> Result<Closure, Located<CompileError>> {
+    compile_with(program, level, IRCompiler::new())
+}
+/// Same as `compile` but with a prepared compiler, e.g. one checking a `CancelToken`
+pub fn compile_with(
+    program: &Located<Program>,
+    level: OptLevel,
+    mut compiler: IRCompiler,
+) -> Result<Closure, Located<CompileError>> {
+    program.compile(&mut compiler)?;
+    compiler.write(IR::Return { src: None }, program.pos.clone());
+    let mut closure = compiler.pop_closure().expect("closure stack is empty");
+    optimize::optimize(&mut closure, level);
+    Ok(closure)
+}
+
+impl Compilable for Program {
+    type Output = ();
+    fn compile(
+        &self,
+        _: &Position,
+        compiler: &mut IRCompiler,
+    ) -> Result<Self::Output, Located<CompileError>> {
+        compile_block(&self.0, compiler)
+    }
+}
+impl Compilable for Statement {
+    type Output = ();
+    fn compile(
+        &self,
+        pos: &Position,
+        compiler: &mut IRCompiler,
+    ) -> Result<Self::Output, Located<CompileError>> {
+        let pos = pos.clone();
+        if compiler.is_cancelled() {
+            return Err(Located::new(CompileError::Cancelled, pos));
+        }
+        match self {
+            Self::Assign { path, expr } => match &path.value {
+                Path::Ident(ident) => {
+                    let src = expr.compile(compiler)?;
+                    assign(ident, src, pos, compiler);
+                }
+                Path::Field { head, field } => {
+                    let head = head.compile(compiler)?;
+                    if let Atom::Path(Path::Ident(ident)) = &field.value {
+                        let addr = compiler.add_string(ident.clone());
+                        let src = expr.compile(compiler)?;
+                        compiler.write(IR::SetFieldString { head, addr, src }, pos);
+                    } else {
+                        let field = field.compile(compiler)?;
+                        let src = expr.compile(compiler)?;
+                        compiler.write(IR::SetField { head, field, src }, pos);
+                    }
+                }
+                Path::Index { head, index } => {
+                    let head = head.compile(compiler)?;
+                    let index = index.compile(compiler)?;
+                    let src = expr.compile(compiler)?;
+                    compiler.write(IR::SetIndex { head, index, src }, pos);
+                }
+            },
+            Self::Call { head, args } => {
+                let func = head.compile(compiler)?;
+                compile_call(func, args, false, pos, compiler)?;
+            }
+            Self::If {
+                cond,
+                case,
+                else_case,
+            } => {
+                let cond = cond.compile(compiler)?;
+                let end = compiler.new_label();
+                let next = match else_case {
+                    Some(_) => compiler.new_label(),
+                    None => end,
+                };
+                compiler.write(
+                    IR::JumpIf {
+                        negative: true,
+                        cond,
+                        addr: next,
+                    },
+                    pos.clone(),
+                );
+                compile_scope(case, false, compiler)?;
+                if let Some(else_case) = else_case {
+                    compiler.write(IR::Jump { addr: end }, pos.clone());
+                    compiler.set_label(next, pos.clone());
+                    compile_scope(else_case, false, compiler)?;
+                }
+                compiler.set_label(end, pos);
+            }
+            Self::While { cond, body } => {
+                let start = compiler.new_label();
+                let end = compiler.new_label();
+                compiler.set_label(start, pos.clone());
+                let cond = cond.compile(compiler)?;
+                compiler.write(
+                    IR::JumpIf {
+                        negative: true,
+                        cond,
+                        addr: end,
+                    },
+                    pos.clone(),
+                );
+                compiler
+                    .loops_mut()
+                    .expect("closure stack is empty")
+                    .push((start, end));
+                let body = compile_scope(body, false, compiler);
+                compiler.loops_mut().expect("closure stack is empty").pop();
+                body?;
+                compiler.write(IR::Jump { addr: start }, pos.clone());
+                compiler.set_label(end, pos);
+            }
+            Self::For { var, iter, body } => {
+                let src = iter.compile(compiler)?;
+                compiler.with_temp(|compiler, iter| {
+                    compiler.write(IR::IterInit { dst: iter, src }, pos.clone());
+                    let start = compiler.new_label();
+                    let end = compiler.new_label();
+                    compiler.set_label(start, pos.clone());
+                    compiler.push_scope(false);
+                    let dst = compiler.alloc_register();
+                    compiler.declare(var.value.clone(), dst);
+                    compiler.write(
+                        IR::IterNext {
+                            dst,
+                            iter,
+                            addr: end,
+                        },
+                        var.pos.clone(),
+                    );
+                    compiler
+                        .loops_mut()
+                        .expect("closure stack is empty")
+                        .push((start, end));
+                    let body = compile_block(body, compiler);
+                    compiler.loops_mut().expect("closure stack is empty").pop();
+                    compiler.pop_scope();
+                    body?;
+                    compiler.write(IR::Jump { addr: start }, pos.clone());
+                    compiler.set_label(end, pos);
+                    Ok(())
+                })?;
+            }
+            Self::Function { name, params, body } => {
+                let addr = compile_function(params, body, &pos, compiler)?;
+                let dst = compiler.alloc_register();
+                compiler.write(IR::Closure { dst, addr }, pos.clone());
+                assign(&name.value, dst, pos, compiler);
+            }
+            Self::Break => {
+                let Some(&(_, end)) = compiler.loops().and_then(|loops| loops.last()) else {
+                    return Err(Located::new(CompileError::BreakOutsideLoop, pos));
+                };
+                compiler.write(IR::Jump { addr: end }, pos);
+            }
+            Self::Continue => {
+                let Some(&(start, _)) = compiler.loops().and_then(|loops| loops.last()) else {
+                    return Err(Located::new(CompileError::ContinueOutsideLoop, pos));
+                };
+                compiler.write(IR::Jump { addr: start }, pos);
+            }
+            Self::Block(stats) => compile_scope(stats, true, compiler)?,
+            // a top-level return ends the program with `src` as its result
+            Self::Return(expr) => {
+                let src = match expr {
+                    Some(expr) => Some(expr.compile(compiler)?),
+                    None => None,
+                };
+                compiler.write(IR::Return { src }, pos);
+            }
+        }
+        Ok(())
+    }
+}
+impl Compilable for Expression {
+    type Output = usize;
+    fn compile(
+        &self,
+        pos: &Position,
+        compiler: &mut IRCompiler,
+    ) -> Result<Self::Output, Located<CompileError>> {
+        let pos = pos.clone();
+        if compiler.is_cancelled() {
+            return Err(Located::new(CompileError::Cancelled, pos));
+        }
+        match self {
+            Self::Atom(atom) => atom.compile(&pos, compiler),
+            Self::Call { head, args } => {
+                let func = head.compile(compiler)?;
+                let dst = compile_call(func, args, true, pos, compiler)?;
+                Ok(dst.expect("call result was requested"))
+            }
+            Self::Binary { op, left, right } => {
+                let left = left.compile(compiler)?;
+                let right = right.compile(compiler)?;
+                let dst = compiler.alloc_register();
+                compiler.write(
+                    IR::Binary {
+                        op: *op,
+                        dst,
+                        left,
+                        right,
+                    },
+                    pos,
+                );
+                Ok(dst)
+            }
+            Self::Unary { op, expr } => {
+                let src = expr.compile(compiler)?;
+                let dst = compiler.alloc_register();
+                compiler.write(IR::Unary { op: *op, dst, src }, pos);
+                Ok(dst)
+            }
+            Self::Function { params, body } => {
+                let addr = compile_function(params, body, &pos, compiler)?;
+                let dst = compiler.alloc_register();
+                compiler.write(IR::Closure { dst, addr }, pos);
+                Ok(dst)
+            }
+            Self::Field { head, field } => {
+                let head = head.compile(compiler)?;
+                compile_field(head, field, pos, compiler)
+            }
+            Self::Index { head, index } => {
+                let head = head.compile(compiler)?;
+                let index = index.compile(compiler)?;
+                let dst = compiler.alloc_register();
+                compiler.write(IR::Index { dst, head, index }, pos);
+                Ok(dst)
+            }
+            Self::Spread(_) => Err(Located::new(CompileError::MisplacedSpread, pos)),
+        }
+    }
+}
+impl Compilable for Atom {
+    type Output = usize;
+    fn compile(
+        &self,
+        pos: &Position,
+        compiler: &mut IRCompiler,
+    ) -> Result<Self::Output, Located<CompileError>> {
+        let pos = pos.clone();
+        match self {
+            Self::Path(path) => path.compile(&pos, compiler),
+            Self::Integer(int) => {
+                let dst = compiler.alloc_register();
+                let addr = compiler.add_int(*int);
+                compiler.write(IR::Int { dst, addr }, pos);
+                Ok(dst)
+            }
+            Self::Decimal(float) => {
+                let dst = compiler.alloc_register();
+                let addr = compiler.add_float(*float);
+                compiler.write(IR::Float { dst, addr }, pos);
+                Ok(dst)
+            }
+            Self::String(string) => {
+                let dst = compiler.alloc_register();
+                let addr = compiler.add_string(string.clone());
+                compiler.write(IR::String { dst, addr }, pos);
+                Ok(dst)
+            }
+            Self::Bool(value) => {
+                let dst = compiler.alloc_register();
+                compiler.write(IR::Bool { dst, value: *value }, pos);
+                Ok(dst)
+            }
+            Self::Null => {
+                let dst = compiler.alloc_register();
+                compiler.write(IR::Null { dst }, pos);
+                Ok(dst)
+            }
+            Self::Expression(expr) => expr.compile(compiler),
+            Self::List(exprs) => {
+                let items: Vec<Item> = exprs.iter().map(|expr| (None, expr)).collect();
+                compile_items(&items, None, &pos, compiler)
+            }
+            Self::Map(pairs) => {
+                let dst = compiler.alloc_register();
+                compiler.write(IR::Map { dst }, pos);
+                for (key, expr) in pairs {
+                    let addr = compiler.add_string(key.value.clone());
+                    let src = expr.compile(compiler)?;
+                    compiler.write(
+                        IR::SetFieldString {
+                            head: dst,
+                            addr,
+                            src,
+                        },
+                        key.pos.clone(),
+                    );
+                }
+                Ok(dst)
+            }
+        }
+    }
+}
+impl Compilable for Path {
+    type Output = usize;
+    fn compile(
+        &self,
+        pos: &Position,
+        compiler: &mut IRCompiler,
+    ) -> Result<Self::Output, Located<CompileError>> {
+        let pos = pos.clone();
+        match self {
+            Self::Ident(ident) => {
+                if let Some(register) = compiler.local(ident) {
+                    return Ok(register);
+                }
+                if compiler.enclosing_local(ident) {
+                    return Err(Located::new(
+                        CompileError::CapturedLocal(ident.clone()),
+                        pos,
+                    ));
+                }
+                let dst = compiler.alloc_register();
+                let addr = compiler.add_string(ident.clone());
+                compiler.write(IR::Get { dst, addr }, pos);
+                Ok(dst)
+            }
+            Self::Field { head, field } => {
+                let head = head.compile(compiler)?;
+                compile_field(head, field, pos, compiler)
+            }
+            Self::Index { head, index } => {
+                let head = head.compile(compiler)?;
+                let index = index.compile(compiler)?;
+                let dst = compiler.alloc_register();
+                compiler.write(IR::Index { dst, head, index }, pos);
+                Ok(dst)
+            }
+        }
+    }
+}
+
+/// Reads `field` of the value in `head`, by name if it is a plain identifier
+fn compile_field(
+    head: usize,
+    field: &Located<Atom>,
+    pos: Position,
+    compiler: &mut IRCompiler,
+) -> Result<usize, Located<CompileError>> {
+    let dst = compiler.alloc_register();
+    if let Atom::Path(Path::Ident(ident)) = &field.value {
+        let addr = compiler.add_string(ident.clone());
+        compiler.write(IR::FieldString { dst, head, addr }, pos);
+    } else {
+        let field = field.compile(compiler)?;
+        compiler.write(IR::Field { dst, head, field }, pos);
+    }
+    Ok(dst)
+}
+/// Calls `func` with `args`, returning the result register if `ret` is set. Arguments are
+/// passed through a list with `CallSpread` if any of them is spread.
+fn compile_call(
+    func: usize,
+    args: &[Argument],
+    ret: bool,
+    pos: Position,
+    compiler: &mut IRCompiler,
+) -> Result<Option<usize>, Located<CompileError>> {
+    if !args
+        .iter()
+        .any(|(_, arg)| matches!(arg.value, Expression::Spread(_)))
+    {
+        let (start, amount) = compile_args(args, compiler)?;
+        let dst = ret.then(|| compiler.alloc_register());
+        compiler.write(
+            IR::Call {
+                dst,
+                func,
+                start,
+                amount,
+            },
+            pos,
+        );
+        return Ok(dst);
+    }
+    // the keyword map is appended as a list of one element, created before the arguments are
+    // evaluated so they can all be compiled in order
+    let keywords = args
+        .iter()
+        .any(|(name, _)| name.is_some())
+        .then(|| compiler.alloc_registers(2));
+    if let Some(chunk) = keywords {
+        compiler.write(IR::Map { dst: chunk + 1 }, pos.clone());
+    }
+    let items: Vec<Item> = args
+        .iter()
+        .map(|(name, arg)| (name.as_ref(), arg))
+        .collect();
+    let list = compile_items(&items, keywords.map(|chunk| chunk + 1), &pos, compiler)?;
+    if let Some(chunk) = keywords {
+        compiler.write(
+            IR::List {
+                dst: chunk,
+                length: 1,
+            },
+            pos.clone(),
+        );
+        compiler.write(
+            IR::Extend {
+                dst: list,
+                src: chunk,
+            },
+            pos.clone(),
+        );
+    }
+    let dst = ret.then(|| compiler.alloc_register());
+    compiler.write(
+        IR::CallSpread {
+            dst,
+            func,
+            args: list,
+        },
+        pos,
+    );
+    Ok(dst)
+}
+/// A list item or call argument, named for keyword arguments
+type Item<'a> = (Option<&'a Located<String>>, &'a Located<Expression>);
+/// Compiles `items` into a new list, appending `...` spreads to it with `Extend`. Named items are
+/// set on the keyword `map` instead, in order with the others
+fn compile_items(
+    items: &[Item],
+    map: Option<usize>,
+    pos: &Position,
+    compiler: &mut IRCompiler,
+) -> Result<usize, Located<CompileError>> {
+    let mut list = None;
+    let mut rest = items;
+    loop {
+        let count = rest
+            .iter()
+            .take_while(|(_, item)| !matches!(item.value, Expression::Spread(_)))
+            .count();
+        let length = rest[..count]
+            .iter()
+            .filter(|(name, _)| name.is_none())
+            .count();
+        // elements are placed in the registers directly following `chunk`
+        let chunk = match list {
+            Some(_) if length == 0 => None,
+            _ => Some(compiler.alloc_registers(length + 1)),
+        };
+        let mut idx = 0;
+        for (name, item) in &rest[..count] {
+            let src = item.compile(compiler)?;
+            match (name, chunk) {
+                (Some(name), _) => {
+                    let addr = compiler.add_string(name.value.clone());
+                    compiler.write(
+                        IR::SetFieldString {
+                            head: map.expect("keyword arguments without a map"),
+                            addr,
+                            src,
+                        },
+                        name.pos.clone(),
+                    );
+                }
+                (None, Some(chunk)) => {
+                    compiler.write(
+                        IR::Move {
+                            dst: chunk + 1 + idx,
+                            src,
+                        },
+                        item.pos.clone(),
+                    );
+                    idx += 1;
+                }
+                (None, None) => unreachable!("positional items are placed in a chunk"),
+            }
+        }
+        if let Some(chunk) = chunk {
+            compiler.write(IR::List { dst: chunk, length }, pos.clone());
+            if let Some(dst) = list {
+                compiler.write(IR::Extend { dst, src: chunk }, pos.clone());
+            }
+        }
+        let dst = *list.get_or_insert_with(|| chunk.expect("the first chunk is always placed"));
+        let Some(((_, spread), tail)) = rest[count..].split_first() else {
+            return Ok(dst);
+        };
+        let Expression::Spread(expr) = &spread.value else {
+            unreachable!("chunks end at a spread")
+        };
+        let src = expr.compile(compiler)?;
+        compiler.write(IR::Extend { dst, src }, spread.pos.clone());
+        rest = tail;
+    }
+}
+/// Compiles the positional `args` into consecutive registers, followed by a map of the
+/// keyword arguments if there are any, returning the first register and the amount
+fn compile_args(
+    args: &[Argument],
+    compiler: &mut IRCompiler,
+) -> Result<(usize, usize), Located<CompileError>> {
+    let positional = args.iter().filter(|(name, _)| name.is_none()).count();
+    let keywords = positional < args.len();
+    let amount = positional + usize::from(keywords);
+    let start = compiler.alloc_registers(amount);
+    let map = start + positional;
+    if keywords {
+        let pos = args
+            .iter()
+            .find_map(|(name, _)| name.as_ref())
+            .map(|name| name.pos.clone());
+        compiler.write(IR::Map { dst: map }, pos.unwrap_or_default());
+    }
+    let mut idx = 0;
+    for (name, arg) in args {
+        let src = arg.compile(compiler)?;
+        if let Some(name) = name {
+            let addr = compiler.add_string(name.value.clone());
+            compiler.write(
+                IR::SetFieldString {
+                    head: map,
+                    addr,
+                    src,
+                },
+                name.pos.clone(),
+            );
+        } else {
+            compiler.write(
+                IR::Move {
+                    dst: start + idx,
+                    src,
+                },
+                arg.pos.clone(),
+            );
+            idx += 1;
+        }
+    }
+    Ok((start, amount))
+}
+fn compile_block(
+    stats: &[Located<Statement>],
+    compiler: &mut IRCompiler,
+) -> Result<(), Located<CompileError>> {
+    for stat in stats {
+        // temporaries don't outlive the statement using them
+        let registers = compiler.registers().cloned().unwrap_or_default();
+        stat.compile(compiler)?;
+        compiler.free_registers_since(&registers);
+    }
+    Ok(())
+}
+/// Compiles `stats` in a new scope, freeing its registers and locals afterwards. Only `{}` blocks
+/// are `declaring`, so that loop and branch bodies can still update the globals around them.
+fn compile_scope(
+    stats: &[Located<Statement>],
+    declaring: bool,
+    compiler: &mut IRCompiler,
+) -> Result<(), Located<CompileError>> {
+    compiler.push_scope(declaring);
+    let result = compile_block(stats, compiler);
+    compiler.pop_scope();
+    result
+}
+/// Stores `src` into the local variable `name` if there is one. Otherwise `name` is declared as
+/// a local of the innermost function or block scope, outliving the branch or loop it is assigned
+/// in, and set as a global outside of both.
+fn assign(name: &str, src: usize, pos: Position, compiler: &mut IRCompiler) {
+    if let Some(dst) = compiler.local(name) {
+        compiler.write(IR::Move { dst, src }, pos);
+    } else if compiler.declaring() {
+        let dst = compiler.alloc_register();
+        compiler.declare_local(name.to_string(), dst);
+        compiler.write(IR::Move { dst, src }, pos);
+    } else {
+        let addr = compiler.add_string(name.to_string());
+        compiler.write(IR::Set { addr, src }, pos);
+    }
+}
+/// Compiles a function body into a new closure, returning its index in the parent's closures
+fn compile_function(
+    params: &[Located<String>],
+    body: &[Located<Statement>],
+    pos: &Position,
+    compiler: &mut IRCompiler,
+) -> Result<usize, Located<CompileError>> {
+    compiler.push_closure();
+    let start = compiler.alloc_registers(params.len());
+    for (idx, param) in params.iter().enumerate() {
+        compiler.declare(param.value.clone(), start + idx);
+    }
+    let result = compile_block(body, compiler);
+    compiler.write(IR::Return { src: None }, pos.clone());
+    let mut closure = compiler.pop_closure().expect("closure stack is empty");
+    result?;
+    closure.params = params.len();
+    let parent = compiler.closure_mut().expect("closure stack is empty");
+    parent.closures.push(Arc::new(closure));
+    Ok(parent.closures.len() - 1)
+}
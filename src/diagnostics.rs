@@ -0,0 +1,63 @@
+use std::fmt::{Display, Write};
+
+use crate::{position::Located, Error};
+
+#[cfg(feature = "color")]
+mod style {
+    pub const ERROR: &str = "\x1b[1;31m";
+    pub const GUTTER: &str = "\x1b[1;34m";
+    pub const BOLD: &str = "\x1b[1m";
+    pub const RESET: &str = "\x1b[0m";
+}
+#[cfg(not(feature = "color"))]
+mod style {
+    pub const ERROR: &str = "";
+    pub const GUTTER: &str = "";
+    pub const BOLD: &str = "";
+    pub const RESET: &str = "";
+}
+use style::{BOLD, ERROR, GUTTER, RESET};
+
+/// Renders `err` like rustc does: the message, the location and the spanned lines of `src`
+/// with the span underlined by carets
+pub fn render<E: Display>(err: &Located<E>, src: &str) -> String {
+    let pos = &err.pos;
+    let lines: Vec<&str> = src.split('\n').collect();
+    let last = pos.ln.end.max(pos.ln.start);
+    let width = (last + 1).to_string().len();
+    let mut report = format!("{ERROR}error{RESET}{BOLD}: {}{RESET}\n", err.value);
+    let _ = writeln!(
+        report,
+        "{GUTTER}{:width$}-->{RESET} {}:{}",
+        "",
+        pos.ln.start + 1,
+        pos.col.start + 1
+    );
+    let _ = writeln!(report, "{GUTTER}{:width$} |{RESET}", "");
+    for ln in pos.ln.start..=last {
+        let line = lines.get(ln).copied().unwrap_or_default();
+        let len = line.chars().count();
+        let start = if ln == pos.ln.start { pos.col.start } else { 0 };
+        let end = if ln == last { pos.col.end } else { len };
+        // empty spans still get a caret
+        let carets = end.saturating_sub(start).max(1);
+        let _ = writeln!(report, "{GUTTER}{:>width$} |{RESET} {line}", ln + 1);
+        let _ = writeln!(
+            report,
+            "{GUTTER}{:width$} |{RESET} {:start$}{ERROR}{}{RESET}",
+            "",
+            "",
+            "^".repeat(carets)
+        );
+    }
+    report
+}
+/// Renders any error of the pipeline from `src` to a closure, see `render`
+pub fn render_error(err: &Error, src: &str) -> String {
+    match err {
+        Error::Lex(err) => render(err, src),
+        Error::Parse(err) => render(err, src),
+        Error::Compile(err) => render(err, src),
+        Error::Link(err) => render(err, src),
+    }
+}
@@ -0,0 +1,229 @@
+use crate::lexer::Token;
+
+/// Programmatic description of the grammar accepted by `lexer` and `parser`,
+/// for generating editor grammars and documentation
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenKind {
+    pub name: &'static str,
+    /// Literal text for fixed tokens, a regular expression otherwise
+    pub pattern: &'static str,
+    pub literal: bool,
+}
+#[derive(Debug, Clone, PartialEq)]
+pub struct Production {
+    pub name: &'static str,
+    /// EBNF alternatives referring to productions by name and tokens by kind name or literal
+    pub alternatives: &'static [&'static str],
+}
+
+/// Binary operator with its binding power, higher binds tighter
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operator {
+    pub symbol: &'static str,
+    pub precedence: u8,
+    pub left_assoc: bool,
+}
+
+pub const COMMENT: &str = r"#[^\n]*";
+
+pub const TOKENS: &[TokenKind] = &[
+    TokenKind { name: "Ident", pattern: r"[A-Za-z][A-Za-z0-9]*", literal: false },
+    TokenKind { name: "Integer", pattern: r"[0-9]+", literal: false },
+    TokenKind { name: "Decimal", pattern: r"[0-9]+\.[0-9]*", literal: false },
+    TokenKind { name: "String", pattern: r#""([^"\\]|\\.)*"|'([^'\\]|\\.)*'"#, literal: false },
+    TokenKind { name: "Bool", pattern: "true|false", literal: false },
+    TokenKind { name: "Null", pattern: "null", literal: true },
+    TokenKind { name: "ParanLeft", pattern: "(", literal: true },
+    TokenKind { name: "ParanRight", pattern: ")", literal: true },
+    TokenKind { name: "BracketLeft", pattern: "[", literal: true },
+    TokenKind { name: "BracketRight", pattern: "]", literal: true },
+    TokenKind { name: "BraceLeft", pattern: "{", literal: true },
+    TokenKind { name: "BraceRight", pattern: "}", literal: true },
+    TokenKind { name: "Equal", pattern: "=", literal: true },
+    TokenKind { name: "Semicolon", pattern: ";", literal: true },
+    TokenKind { name: "Dot", pattern: ".", literal: true },
+    TokenKind { name: "Pipe", pattern: "|>", literal: true },
+    TokenKind { name: "Comma", pattern: ",", literal: true },
+    TokenKind { name: "Plus", pattern: "+", literal: true },
+    TokenKind { name: "Minus", pattern: "-", literal: true },
+    TokenKind { name: "Star", pattern: "*", literal: true },
+    TokenKind { name: "Slash", pattern: "/", literal: true },
+    TokenKind { name: "Percent", pattern: "%", literal: true },
+    TokenKind { name: "EqualEqual", pattern: "==", literal: true },
+    TokenKind { name: "ExclamationEqual", pattern: "!=", literal: true },
+    TokenKind { name: "Less", pattern: "<", literal: true },
+    TokenKind { name: "LessEqual", pattern: "<=", literal: true },
+    TokenKind { name: "Greater", pattern: ">", literal: true },
+    TokenKind { name: "GreaterEqual", pattern: ">=", literal: true },
+    TokenKind { name: "Exclamation", pattern: "!", literal: true },
+    TokenKind { name: "If", pattern: "if", literal: true },
+    TokenKind { name: "Else", pattern: "else", literal: true },
+    TokenKind { name: "While", pattern: "while", literal: true },
+    TokenKind { name: "Fn", pattern: "fn", literal: true },
+    TokenKind { name: "For", pattern: "for", literal: true },
+    TokenKind { name: "In", pattern: "in", literal: true },
+    TokenKind { name: "Break", pattern: "break", literal: true },
+    TokenKind { name: "Continue", pattern: "continue", literal: true },
+    TokenKind { name: "Return", pattern: "return", literal: true },
+    TokenKind { name: "Ellipsis", pattern: "...", literal: true },
+];
+
+pub const PRODUCTIONS: &[Production] = &[
+    Production { name: "program", alternatives: &["{ statement }"] },
+    Production {
+        name: "statement",
+        alternatives: &[
+            "Ident \"=\" expression \";\"",
+            "postfix ( \".\" ( Ident | atom ) | \"[\" expression \"]\" ) \"=\" expression \";\"",
+            "postfix \"(\" arguments \")\" \";\"",
+            "if",
+            "\"while\" expression block",
+            "\"for\" Ident \"in\" expression block",
+            "\"fn\" Ident \"(\" params \")\" block",
+            "\"break\" \";\"",
+            "\"continue\" \";\"",
+            "\"return\" [ expression ] \";\"",
+            "block",
+        ],
+    },
+    Production {
+        name: "params",
+        alternatives: &["[ Ident { \",\" Ident } [ \",\" ] ]", "{ Ident }"],
+    },
+    Production { name: "if", alternatives: &["\"if\" expression block [ \"else\" ( block | if ) ]"] },
+    Production { name: "block", alternatives: &["\"{\" { statement } \"}\""] },
+    Production {
+        name: "arguments",
+        alternatives: &[
+            "[ argument { \",\" argument } [ \",\" ] ]",
+            "{ argument }",
+        ],
+    },
+    Production {
+        name: "argument",
+        alternatives: &["[ Ident \"=\" ] expression", "\"...\" expression"],
+    },
+    Production {
+        name: "items",
+        alternatives: &["[ item { \",\" item } [ \",\" ] ]", "{ item }"],
+    },
+    Production { name: "item", alternatives: &["[ \"...\" ] expression"] },
+    Production {
+        name: "expression",
+        alternatives: &["binary { \"|>\" atom [ \"(\" arguments \")\" ] }"],
+    },
+    Production {
+        name: "binary",
+        alternatives: &["unary { operator unary }"],
+    },
+    Production {
+        name: "operator",
+        alternatives: &[
+            "\"+\"", "\"-\"", "\"*\"", "\"/\"", "\"%\"", "\"==\"", "\"!=\"", "\"<\"", "\"<=\"", "\">\"",
+            "\">=\"",
+        ],
+    },
+    Production { name: "unary", alternatives: &["( \"-\" | \"!\" ) unary", "postfix"] },
+    Production {
+        name: "postfix",
+        alternatives: &["( atom | lambda ) { \"(\" arguments \")\" | \"[\" expression \"]\" | \".\" ( Ident | atom ) }"],
+    },
+    Production { name: "lambda", alternatives: &["\"fn\" \"(\" params \")\" block"] },
+    Production {
+        name: "atom",
+        alternatives: &[
+            "path",
+            "Integer",
+            "Decimal",
+            "String",
+            "Bool",
+            "Null",
+            "\"(\" expression \")\"",
+            "\"[\" items \"]\"",
+            "\"{\" [ entry { \";\" entry } [ \";\" ] ] \"}\"",
+        ],
+    },
+    Production { name: "entry", alternatives: &["( Ident | String ) \"=\" expression"] },
+    Production { name: "path", alternatives: &["Ident { \".\" ( Ident | atom ) | \"[\" expression \"]\" }"] },
+];
+
+pub const BINARY_OPERATORS: &[Operator] = &[
+    Operator { symbol: "==", precedence: 1, left_assoc: true },
+    Operator { symbol: "!=", precedence: 1, left_assoc: true },
+    Operator { symbol: "<", precedence: 1, left_assoc: true },
+    Operator { symbol: "<=", precedence: 1, left_assoc: true },
+    Operator { symbol: ">", precedence: 1, left_assoc: true },
+    Operator { symbol: ">=", precedence: 1, left_assoc: true },
+    Operator { symbol: "+", precedence: 2, left_assoc: true },
+    Operator { symbol: "-", precedence: 2, left_assoc: true },
+    Operator { symbol: "*", precedence: 3, left_assoc: true },
+    Operator { symbol: "/", precedence: 3, left_assoc: true },
+    Operator { symbol: "%", precedence: 3, left_assoc: true },
+];
+
+pub fn token_kind(name: &str) -> Option<&'static TokenKind> {
+    TOKENS.iter().find(|kind| kind.name == name)
+}
+pub fn production(name: &str) -> Option<&'static Production> {
+    PRODUCTIONS.iter().find(|production| production.name == name)
+}
+/// Renders the productions as EBNF, one rule per production
+pub fn ebnf() -> String {
+    PRODUCTIONS
+        .iter()
+        .map(|production| {
+            format!(
+                "{} = {} ;\n",
+                production.name,
+                production.alternatives.join("\n    | ")
+            )
+        })
+        .collect()
+}
+
+impl Token {
+    /// Name of this token's entry in `TOKENS`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Ident(_) => "Ident",
+            Self::Integer(_) => "Integer",
+            Self::Decimal(_) => "Decimal",
+            Self::String(_) => "String",
+            Self::Bool(_) => "Bool",
+            Self::Null => "Null",
+            Self::ParanLeft => "ParanLeft",
+            Self::ParanRight => "ParanRight",
+            Self::BracketLeft => "BracketLeft",
+            Self::BracketRight => "BracketRight",
+            Self::BraceLeft => "BraceLeft",
+            Self::BraceRight => "BraceRight",
+            Self::Equal => "Equal",
+            Self::Semicolon => "Semicolon",
+            Self::Dot => "Dot",
+            Self::Pipe => "Pipe",
+            Self::Comma => "Comma",
+            Self::Plus => "Plus",
+            Self::Minus => "Minus",
+            Self::Star => "Star",
+            Self::Slash => "Slash",
+            Self::Percent => "Percent",
+            Self::EqualEqual => "EqualEqual",
+            Self::ExclamationEqual => "ExclamationEqual",
+            Self::Less => "Less",
+            Self::LessEqual => "LessEqual",
+            Self::Greater => "Greater",
+            Self::GreaterEqual => "GreaterEqual",
+            Self::Exclamation => "Exclamation",
+            Self::If => "If",
+            Self::Else => "Else",
+            Self::While => "While",
+            Self::Fn => "Fn",
+            Self::For => "For",
+            Self::In => "In",
+            Self::Break => "Break",
+            Self::Continue => "Continue",
+            Self::Return => "Return",
+            Self::Ellipsis => "Ellipsis",
+        }
+    }
+}
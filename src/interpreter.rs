@@ -0,0 +1,535 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fmt::{Debug, Display},
+    io::Write,
+    rc::Rc,
+    sync::Arc,
+    vec::IntoIter,
+};
+
+use crate::{
+    ir::{Closure, IR},
+    parser::{BinaryOperator, UnaryOperator},
+    position::Located,
+};
+
+pub mod stdlib;
+pub mod value;
+use value::{Native, Value};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    UndefinedGlobal(String),
+    NotCallable(&'static str),
+    NotIterable(&'static str),
+    /// Operator applied to values of the given types
+    InvalidBinary(BinaryOperator, &'static str, &'static str),
+    InvalidUnary(UnaryOperator, &'static str),
+    /// Value of the first type indexed with one of the second
+    InvalidIndex(&'static str, &'static str),
+    InvalidExtend(&'static str, &'static str),
+    IndexOutOfRange(i64, usize),
+    DivisionByZero,
+    Overflow,
+    /// Function called with the wrong number of arguments, expected and given
+    ArgumentCount(usize, usize),
+    /// Argument at the index has a type the native function doesn't accept
+    InvalidArgument(usize, &'static str),
+    LimitExceeded(Limit),
+    /// Raised by a native function
+    Custom(String),
+}
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UndefinedGlobal(name) => write!(f, "undefined variable `{name}`"),
+            Self::NotCallable(kind) => write!(f, "cannot call a value of type {kind}"),
+            Self::NotIterable(kind) => write!(f, "cannot iterate over a value of type {kind}"),
+            Self::InvalidBinary(op, left, right) => {
+                write!(f, "cannot apply `{op}` to {left} and {right}")
+            }
+            Self::InvalidUnary(op, kind) => write!(f, "cannot apply `{op}` to {kind}"),
+            Self::InvalidIndex(head, index) => write!(f, "cannot index {head} with {index}"),
+            Self::InvalidExtend(dst, src) => write!(f, "cannot extend {dst} with {src}"),
+            Self::IndexOutOfRange(idx, len) => {
+                write!(f, "index {idx} is out of range for length {len}")
+            }
+            Self::DivisionByZero => write!(f, "division by zero"),
+            Self::Overflow => write!(f, "integer overflow"),
+            Self::ArgumentCount(expected, got) => {
+                write!(f, "expected {expected} arguments, got {got}")
+            }
+            Self::InvalidArgument(idx, kind) => {
+                write!(f, "argument {} can't be of type {kind}", idx + 1)
+            }
+            Self::LimitExceeded(limit) => write!(f, "{limit} limit exceeded"),
+            Self::Custom(message) => write!(f, "{message}"),
+        }
+    }
+}
+impl std::error::Error for RuntimeError {}
+
+/// A closure whose nested closures are shared, so creating function values is cheap
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub closure: Arc<Closure>,
+    pub closures: Vec<Rc<Function>>,
+}
+impl From<Arc<Closure>> for Function {
+    fn from(closure: Arc<Closure>) -> Self {
+        let closures = closure
+            .closures
+            .iter()
+            .map(|closure| Rc::new(Self::from(Arc::clone(closure))))
+            .collect();
+        Self { closure, closures }
+    }
+}
+
+#[derive(Debug)]
+struct Frame {
+    function: Rc<Function>,
+    registers: Vec<Value>,
+    /// Iterators created by `IterInit`, keyed by their register
+    iterators: HashMap<usize, IntoIter<Value>>,
+    ip: usize,
+    /// Register of the caller's frame receiving the return value
+    ret: Option<usize>,
+}
+impl Frame {
+    fn new(function: Rc<Function>, mut args: Vec<Value>, ret: Option<usize>) -> Self {
+        let closure = &function.closure;
+        args.resize(closure.registers.max(closure.params), Value::Null);
+        Self {
+            function,
+            registers: args,
+            iterators: HashMap::new(),
+            ip: 0,
+            ret,
+        }
+    }
+}
+
+/// Upper bounds for running untrusted scripts, checked anew on every run
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limits {
+    pub max_instructions: usize,
+    /// Nested calls of script functions, natives don't count
+    pub max_call_depth: usize,
+    /// Lists and maps created plus the elements they grow by, so growing one container counts
+    /// like creating many
+    pub max_allocations: usize,
+    /// Length in bytes of strings produced by operators and natives
+    pub max_string_len: usize,
+}
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_instructions: usize::MAX,
+            max_call_depth: usize::MAX,
+            max_allocations: usize::MAX,
+            max_string_len: usize::MAX,
+        }
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Instructions,
+    CallDepth,
+    Allocations,
+    StringLength,
+}
+impl Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Instructions => write!(f, "instruction"),
+            Self::CallDepth => write!(f, "call depth"),
+            Self::Allocations => write!(f, "allocation"),
+            Self::StringLength => write!(f, "string length"),
+        }
+    }
+}
+
+/// Writer shared by the interpreter and the natives printing to it
+pub type Output = Rc<RefCell<Box<dyn Write>>>;
+
+pub struct Interpreter {
+    pub globals: HashMap<String, Value>,
+    pub limits: Limits,
+    frames: Vec<Frame>,
+    /// Instructions executed and lists, maps and their elements allocated in the current run
+    instructions: usize,
+    allocations: usize,
+    /// Where `print` writes, stdout unless replaced
+    output: Output,
+}
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self {
+            globals: HashMap::new(),
+            limits: Limits::default(),
+            frames: vec![],
+            instructions: 0,
+            allocations: 0,
+            output: Rc::new(RefCell::new(Box::new(std::io::stdout()))),
+        }
+    }
+}
+impl Debug for Interpreter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Interpreter")
+            .field("globals", &self.globals)
+            .field("limits", &self.limits)
+            .field("frames", &self.frames)
+            .field("instructions", &self.instructions)
+            .field("allocations", &self.allocations)
+            .finish_non_exhaustive()
+    }
+}
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+    /// Sends the output of `print` to `writer`, also for natives registered before
+    pub fn with_output(self, writer: impl Write + 'static) -> Self {
+        *self.output.borrow_mut() = Box::new(writer);
+        self
+    }
+    /// Handle to the writer `print` uses, for natives that write output of their own
+    pub fn output(&self) -> Output {
+        Rc::clone(&self.output)
+    }
+    /// Interpreter with the functions of the standard library bound as globals
+    pub fn with_stdlib() -> Self {
+        let mut interpreter = Self::new();
+        stdlib::register(&mut interpreter);
+        interpreter
+    }
+    /// Binds the global `name` to a host function, callable from scripts like any other
+    pub fn register(
+        &mut self,
+        name: &str,
+        func: impl Fn(&[Value]) -> Result<Value, RuntimeError> + 'static,
+    ) {
+        let native = Native {
+            name: name.to_string(),
+            func: Box::new(func),
+        };
+        self.globals
+            .insert(name.to_string(), Value::Native(Rc::new(native)));
+    }
+    /// Runs `closure` to its end, returning the value of a top-level `return`. Expects resolved
+    /// labels, globals set by the program are kept for later runs
+    pub fn run(&mut self, closure: &Arc<Closure>) -> Result<Value, Located<RuntimeError>> {
+        let function = Rc::new(Function::from(Arc::clone(closure)));
+        self.frames = vec![Frame::new(function, vec![], None)];
+        self.instructions = 0;
+        self.allocations = 0;
+        let result = self.execute();
+        self.frames.clear();
+        result
+    }
+    /// Steps until the program ends, locating errors at the instruction raising them
+    fn execute(&mut self) -> Result<Value, Located<RuntimeError>> {
+        loop {
+            match self.step() {
+                Ok(Some(value)) => return Ok(value),
+                Ok(None) => {}
+                Err(err) => {
+                    let frame = self.frames.last().expect("frame stack is empty");
+                    let pos = frame.function.closure.position(frame.ip.saturating_sub(1));
+                    return Err(Located::new(err, pos.cloned().unwrap_or_default()));
+                }
+            }
+        }
+    }
+    /// Executes the next instruction, returning the program's result once it ends
+    fn step(&mut self) -> Result<Option<Value>, RuntimeError> {
+        let frame = self.frames.last_mut().expect("frame stack is empty");
+        let function = Rc::clone(&frame.function);
+        let closure = &function.closure;
+        let Some(ir) = closure.code.get(frame.ip) else {
+            // running off the end returns like `return;`
+            return Ok(self.ret(Value::Null));
+        };
+        frame.ip += 1;
+        count(
+            &mut self.instructions,
+            1,
+            self.limits.max_instructions,
+            Limit::Instructions,
+        )?;
+        let registers = &mut frame.registers;
+        match &ir.value.ir {
+            IR::None => {}
+            IR::Jump { addr } => frame.ip = *addr,
+            IR::JumpIf {
+                negative,
+                cond,
+                addr,
+            } => {
+                if registers[*cond].truthy() != *negative {
+                    frame.ip = *addr;
+                }
+            }
+            IR::IterInit { dst, src } => {
+                let values: Vec<Value> = match &registers[*src] {
+                    Value::List(list) => list.borrow().clone(),
+                    Value::Map(map) => map.borrow().keys().cloned().map(Value::String).collect(),
+                    Value::String(string) => string
+                        .chars()
+                        .map(|char| Value::String(char.to_string()))
+                        .collect(),
+                    value => return Err(RuntimeError::NotIterable(value.kind())),
+                };
+                frame.iterators.insert(*dst, values.into_iter());
+            }
+            IR::IterNext { dst, iter, addr } => {
+                match frame.iterators.get_mut(iter).and_then(Iterator::next) {
+                    Some(value) => registers[*dst] = value,
+                    None => frame.ip = *addr,
+                }
+            }
+            IR::Call {
+                dst,
+                func,
+                start,
+                amount,
+            } => {
+                let func = registers[*func].clone();
+                let args = registers[*start..*start + *amount].to_vec();
+                self.call(func, args, *dst)?;
+            }
+            IR::CallSpread { dst, func, args } => {
+                let func = registers[*func].clone();
+                let args = match &registers[*args] {
+                    Value::List(list) => list.borrow().clone(),
+                    value => return Err(RuntimeError::InvalidExtend("list", value.kind())),
+                };
+                self.call(func, args, *dst)?;
+            }
+            IR::Return { src } => {
+                let value = src.map(|src| registers[src].clone()).unwrap_or_default();
+                return Ok(self.ret(value));
+            }
+            IR::Closure { dst, addr } => {
+                registers[*dst] = Value::Function(Rc::clone(&function.closures[*addr]))
+            }
+            IR::Move { dst, src } => registers[*dst] = registers[*src].clone(),
+            IR::Get { dst, addr } => {
+                let name = &closure.string[*addr];
+                registers[*dst] = self
+                    .globals
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| RuntimeError::UndefinedGlobal(name.clone()))?;
+            }
+            IR::Set { addr, src } => {
+                let value = registers[*src].clone();
+                self.globals.insert(closure.string[*addr].clone(), value);
+            }
+            IR::String { dst, addr } => {
+                registers[*dst] = Value::String(closure.string[*addr].clone())
+            }
+            IR::Int { dst, addr } => registers[*dst] = Value::Int(closure.int[*addr]),
+            IR::Float { dst, addr } => registers[*dst] = Value::Float(closure.float[*addr]),
+            IR::Bool { dst, value } => registers[*dst] = Value::Bool(*value),
+            IR::Null { dst } => registers[*dst] = Value::Null,
+            IR::List { dst, length } => {
+                count(
+                    &mut self.allocations,
+                    1 + *length,
+                    self.limits.max_allocations,
+                    Limit::Allocations,
+                )?;
+                registers[*dst] = registers[*dst + 1..=*dst + *length].to_vec().into()
+            }
+            IR::Map { dst } => {
+                count(
+                    &mut self.allocations,
+                    1,
+                    self.limits.max_allocations,
+                    Limit::Allocations,
+                )?;
+                registers[*dst] = Value::from(BTreeMap::new())
+            }
+            IR::Extend { dst, src } => match (&registers[*dst], &registers[*src]) {
+                (Value::List(dst), Value::List(src)) => {
+                    let src = src.borrow().clone();
+                    count(
+                        &mut self.allocations,
+                        src.len(),
+                        self.limits.max_allocations,
+                        Limit::Allocations,
+                    )?;
+                    dst.borrow_mut().extend(src);
+                }
+                (Value::Map(dst), Value::Map(src)) => {
+                    let src = src.borrow().clone();
+                    count(
+                        &mut self.allocations,
+                        src.len(),
+                        self.limits.max_allocations,
+                        Limit::Allocations,
+                    )?;
+                    dst.borrow_mut().extend(src);
+                }
+                (dst, src) => return Err(RuntimeError::InvalidExtend(dst.kind(), src.kind())),
+            },
+            IR::Field { dst, head, field }
+            | IR::Index {
+                dst,
+                head,
+                index: field,
+            } => registers[*dst] = registers[*head].index(&registers[*field])?,
+            IR::FieldString { dst, head, addr } => {
+                let field = Value::String(closure.string[*addr].clone());
+                registers[*dst] = registers[*head].index(&field)?
+            }
+            IR::SetField { head, field, src }
+            | IR::SetIndex {
+                head,
+                index: field,
+                src,
+            } => {
+                let before = size(&registers[*head]);
+                registers[*head].set_index(&registers[*field], registers[*src].clone())?;
+                count(
+                    &mut self.allocations,
+                    size(&registers[*head]) - before,
+                    self.limits.max_allocations,
+                    Limit::Allocations,
+                )?;
+            }
+            IR::SetFieldString { head, addr, src } => {
+                let field = Value::String(closure.string[*addr].clone());
+                let before = size(&registers[*head]);
+                registers[*head].set_index(&field, registers[*src].clone())?;
+                count(
+                    &mut self.allocations,
+                    size(&registers[*head]) - before,
+                    self.limits.max_allocations,
+                    Limit::Allocations,
+                )?;
+            }
+            IR::Binary {
+                op,
+                dst,
+                left,
+                right,
+            } => {
+                let value = registers[*left].binary(*op, &registers[*right])?;
+                check_string(&value, self.limits.max_string_len)?;
+                registers[*dst] = value;
+            }
+            IR::Unary { op, dst, src } => registers[*dst] = registers[*src].unary(*op)?,
+        }
+        Ok(None)
+    }
+    /// Calls `func`, a native right away and a function by pushing its frame
+    fn call(
+        &mut self,
+        func: Value,
+        args: Vec<Value>,
+        dst: Option<usize>,
+    ) -> Result<(), RuntimeError> {
+        match func {
+            Value::Function(function) => {
+                // the frame of the main closure is not a call
+                if self.frames.len() > self.limits.max_call_depth {
+                    return Err(RuntimeError::LimitExceeded(Limit::CallDepth));
+                }
+                // missing arguments are null, extra ones would overwrite the locals
+                if args.len() > function.closure.params {
+                    return Err(RuntimeError::ArgumentCount(
+                        function.closure.params,
+                        args.len(),
+                    ));
+                }
+                self.frames.push(Frame::new(function, args, dst));
+                Ok(())
+            }
+            Value::Native(native) => {
+                let sizes: Vec<usize> = args.iter().map(size).collect();
+                let value = (native.func)(&args)?;
+                check_string(&value, self.limits.max_string_len)?;
+                // natives may grow their arguments or return a new list or map
+                let grown: usize = args
+                    .iter()
+                    .zip(sizes)
+                    .map(|(arg, before)| size(arg).saturating_sub(before))
+                    .sum();
+                let created = match &value {
+                    Value::List(_) | Value::Map(_)
+                        if !args.iter().any(|arg| shares(arg, &value)) =>
+                    {
+                        1 + size(&value)
+                    }
+                    _ => 0,
+                };
+                count(
+                    &mut self.allocations,
+                    grown + created,
+                    self.limits.max_allocations,
+                    Limit::Allocations,
+                )?;
+                let frame = self.frames.last_mut().expect("frame stack is empty");
+                if let Some(dst) = dst {
+                    frame.registers[dst] = value;
+                }
+                Ok(())
+            }
+            value => Err(RuntimeError::NotCallable(value.kind())),
+        }
+    }
+    /// Pops the current frame, passing `value` to its caller. Returns the value if it was the
+    /// last frame
+    fn ret(&mut self, value: Value) -> Option<Value> {
+        let frame = self.frames.pop().expect("frame stack is empty");
+        let Some(caller) = self.frames.last_mut() else {
+            return Some(value);
+        };
+        if let Some(ret) = frame.ret {
+            caller.registers[ret] = value;
+        }
+        None
+    }
+}
+
+/// Adds `amount` to `counter`, failing once it goes past `max`
+fn count(counter: &mut usize, amount: usize, max: usize, limit: Limit) -> Result<(), RuntimeError> {
+    *counter = counter.saturating_add(amount);
+    if *counter > max {
+        return Err(RuntimeError::LimitExceeded(limit));
+    }
+    Ok(())
+}
+/// Number of elements of a list or map
+fn size(value: &Value) -> usize {
+    match value {
+        Value::List(list) => list.borrow().len(),
+        Value::Map(map) => map.borrow().len(),
+        _ => 0,
+    }
+}
+/// Whether both values are the same list or map
+fn shares(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::List(left), Value::List(right)) => Rc::ptr_eq(left, right),
+        (Value::Map(left), Value::Map(right)) => Rc::ptr_eq(left, right),
+        _ => false,
+    }
+}
+fn check_string(value: &Value, max: usize) -> Result<(), RuntimeError> {
+    match value {
+        Value::String(string) if string.len() > max => {
+            Err(RuntimeError::LimitExceeded(Limit::StringLength))
+        }
+        _ => Ok(()),
+    }
+}
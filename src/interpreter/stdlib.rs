@@ -0,0 +1,183 @@
+use std::{cmp::Ordering, io::Write};
+
+use super::{value::Value, Interpreter, Output, RuntimeError};
+use crate::parser::BinaryOperator;
+
+/// Binds the standard library's functions as globals of `interpreter`
+pub fn register(interpreter: &mut Interpreter) {
+    let output = interpreter.output();
+    interpreter.register("print", move |args| print(args, &output));
+    interpreter.register("len", len);
+    interpreter.register("push", push);
+    interpreter.register("keys", keys);
+    interpreter.register("type", kind);
+    interpreter.register("str", str);
+    interpreter.register("int", int);
+    interpreter.register("float", float);
+    interpreter.register("abs", abs);
+    interpreter.register("min", |args| extremum(args, Ordering::Less));
+    interpreter.register("max", |args| extremum(args, Ordering::Greater));
+    interpreter.register("floor", |args| round(args, f64::floor));
+    interpreter.register("ceil", |args| round(args, f64::ceil));
+    interpreter.register("round", |args| round(args, f64::round));
+    interpreter.register("sqrt", sqrt);
+    interpreter.register("pow", pow);
+}
+
+/// The arguments as an array, if there are exactly `N`
+fn expect<const N: usize>(args: &[Value]) -> Result<&[Value; N], RuntimeError> {
+    args.try_into()
+        .map_err(|_| RuntimeError::ArgumentCount(N, args.len()))
+}
+
+/// Writes its arguments to the interpreter's output, separated by spaces
+fn print(args: &[Value], output: &Output) -> Result<Value, RuntimeError> {
+    let line: Vec<String> = args.iter().map(Value::to_string).collect();
+    writeln!(output.borrow_mut(), "{}", line.join(" "))
+        .map_err(|err| RuntimeError::Custom(err.to_string()))?;
+    Ok(Value::Null)
+}
+fn len(args: &[Value]) -> Result<Value, RuntimeError> {
+    let len = match expect::<1>(args)? {
+        [Value::String(string)] => string.chars().count(),
+        [Value::List(list)] => list.borrow().len(),
+        [Value::Map(map)] => map.borrow().len(),
+        [value] => return Err(RuntimeError::InvalidArgument(0, value.kind())),
+    };
+    i64::try_from(len)
+        .map(Value::Int)
+        .map_err(|_| RuntimeError::Overflow)
+}
+/// Appends a value to a list
+fn push(args: &[Value]) -> Result<Value, RuntimeError> {
+    match expect::<2>(args)? {
+        [Value::List(list), value] => {
+            list.borrow_mut().push(value.clone());
+            Ok(Value::Null)
+        }
+        [value, _] => Err(RuntimeError::InvalidArgument(0, value.kind())),
+    }
+}
+/// List of a map's keys in order
+fn keys(args: &[Value]) -> Result<Value, RuntimeError> {
+    match expect::<1>(args)? {
+        [Value::Map(map)] => Ok(map
+            .borrow()
+            .keys()
+            .cloned()
+            .map(Value::String)
+            .collect::<Vec<Value>>()
+            .into()),
+        [value] => Err(RuntimeError::InvalidArgument(0, value.kind())),
+    }
+}
+fn kind(args: &[Value]) -> Result<Value, RuntimeError> {
+    let [value] = expect::<1>(args)?;
+    Ok(Value::String(value.kind().to_string()))
+}
+fn str(args: &[Value]) -> Result<Value, RuntimeError> {
+    let [value] = expect::<1>(args)?;
+    Ok(Value::String(value.to_string()))
+}
+/// Converts numbers, truncating floats, and parses strings
+fn int(args: &[Value]) -> Result<Value, RuntimeError> {
+    match expect::<1>(args)? {
+        [Value::Int(int)] => Ok(Value::Int(*int)),
+        [Value::Float(float)] => truncate(*float).map(Value::Int),
+        [Value::Bool(value)] => Ok(Value::Int(i64::from(*value))),
+        [Value::String(string)] => string
+            .trim()
+            .parse()
+            .map(Value::Int)
+            .map_err(|_| RuntimeError::Custom(format!("cannot convert {string:?} to an int"))),
+        [value] => Err(RuntimeError::InvalidArgument(0, value.kind())),
+    }
+}
+fn float(args: &[Value]) -> Result<Value, RuntimeError> {
+    match expect::<1>(args)? {
+        [Value::Int(int)] => Ok(Value::Float(*int as f64)),
+        [Value::Float(float)] => Ok(Value::Float(*float)),
+        [Value::String(string)] => string
+            .trim()
+            .parse()
+            .map(Value::Float)
+            .map_err(|_| RuntimeError::Custom(format!("cannot convert {string:?} to a float"))),
+        [value] => Err(RuntimeError::InvalidArgument(0, value.kind())),
+    }
+}
+fn truncate(float: f64) -> Result<i64, RuntimeError> {
+    let int = float.trunc();
+    if int.is_finite() && (i64::MIN as f64..i64::MAX as f64).contains(&int) {
+        Ok(int as i64)
+    } else {
+        Err(RuntimeError::Overflow)
+    }
+}
+fn abs(args: &[Value]) -> Result<Value, RuntimeError> {
+    match expect::<1>(args)? {
+        [Value::Int(int)] => int
+            .checked_abs()
+            .map(Value::Int)
+            .ok_or(RuntimeError::Overflow),
+        [Value::Float(float)] => Ok(Value::Float(float.abs())),
+        [value] => Err(RuntimeError::InvalidArgument(0, value.kind())),
+    }
+}
+/// The smallest or largest of at least one number, by `ordering` relative to the others
+fn extremum(args: &[Value], ordering: Ordering) -> Result<Value, RuntimeError> {
+    let op = match ordering {
+        Ordering::Less => BinaryOperator::Lt,
+        _ => BinaryOperator::Gt,
+    };
+    let Some((first, rest)) = args.split_first() else {
+        return Err(RuntimeError::ArgumentCount(1, 0));
+    };
+    if let Some((idx, value)) = args
+        .iter()
+        .enumerate()
+        .find(|(_, value)| value.number().is_none())
+    {
+        return Err(RuntimeError::InvalidArgument(idx, value.kind()));
+    }
+    let mut best = first;
+    for value in rest {
+        if value.binary(op, best)?.truthy() {
+            best = value;
+        }
+    }
+    Ok(best.clone())
+}
+/// Rounds floats with `f`, ints are already whole
+fn round(args: &[Value], f: fn(f64) -> f64) -> Result<Value, RuntimeError> {
+    match expect::<1>(args)? {
+        [Value::Int(int)] => Ok(Value::Int(*int)),
+        [Value::Float(float)] => Ok(Value::Float(f(*float))),
+        [value] => Err(RuntimeError::InvalidArgument(0, value.kind())),
+    }
+}
+fn sqrt(args: &[Value]) -> Result<Value, RuntimeError> {
+    let [value] = expect::<1>(args)?;
+    value
+        .number()
+        .map(|number| Value::Float(number.sqrt()))
+        .ok_or(RuntimeError::InvalidArgument(0, value.kind()))
+}
+/// Raises the first number to the second, ints with a non-negative exponent stay ints
+fn pow(args: &[Value]) -> Result<Value, RuntimeError> {
+    let [base, exp] = expect::<2>(args)?;
+    if let (Value::Int(base), Value::Int(exp)) = (base, exp) {
+        if let Ok(exp) = u32::try_from(*exp) {
+            return base
+                .checked_pow(exp)
+                .map(Value::Int)
+                .ok_or(RuntimeError::Overflow);
+        }
+    }
+    let base_number = base
+        .number()
+        .ok_or(RuntimeError::InvalidArgument(0, base.kind()))?;
+    let exp_number = exp
+        .number()
+        .ok_or(RuntimeError::InvalidArgument(1, exp.kind()))?;
+    Ok(Value::Float(base_number.powf(exp_number)))
+}
@@ -0,0 +1,311 @@
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    collections::BTreeMap,
+    fmt::{Debug, Display},
+    rc::Rc,
+};
+
+use super::{Function, RuntimeError};
+use crate::parser::{BinaryOperator, UnaryOperator};
+
+pub type NativeFn = dyn Fn(&[Value]) -> Result<Value, RuntimeError>;
+
+#[derive(Clone, Default)]
+pub enum Value {
+    #[default]
+    Null,
+    Int(i64),
+    Float(f64),
+    String(String),
+    Bool(bool),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<BTreeMap<String, Value>>>),
+    Function(Rc<Function>),
+    Native(Rc<Native>),
+}
+/// Function implemented by the host
+pub struct Native {
+    pub name: String,
+    pub func: Box<NativeFn>,
+}
+impl Debug for Native {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Native({:?})", self.name)
+    }
+}
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.eq_visiting(other, false, &mut vec![])
+    }
+}
+/// Strings are written as they are, and quoted when nested in a list or map. A list or map
+/// nested in itself is written as `[...]` or `{...}`.
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_visiting(f, &mut vec![], false)
+    }
+}
+/// Lists and maps are written by their `Display` form, which is safe for cyclic values
+impl Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Null => write!(f, "Null"),
+            Self::Int(int) => write!(f, "Int({int:?})"),
+            Self::Float(float) => write!(f, "Float({float:?})"),
+            Self::String(string) => write!(f, "String({string:?})"),
+            Self::Bool(value) => write!(f, "Bool({value:?})"),
+            Self::List(_) => write!(f, "List({self})"),
+            Self::Map(_) => write!(f, "Map({self})"),
+            Self::Function(function) => write!(f, "Function({function:?})"),
+            Self::Native(native) => write!(f, "{native:?}"),
+        }
+    }
+}
+impl From<Vec<Value>> for Value {
+    fn from(values: Vec<Value>) -> Self {
+        Self::List(Rc::new(RefCell::new(values)))
+    }
+}
+impl From<BTreeMap<String, Value>> for Value {
+    fn from(map: BTreeMap<String, Value>) -> Self {
+        Self::Map(Rc::new(RefCell::new(map)))
+    }
+}
+impl Value {
+    /// Name of the value's type, as shown in errors
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Null => "null",
+            Self::Int(_) => "int",
+            Self::Float(_) => "float",
+            Self::String(_) => "string",
+            Self::Bool(_) => "bool",
+            Self::List(_) => "list",
+            Self::Map(_) => "map",
+            Self::Function(_) | Self::Native(_) => "function",
+        }
+    }
+    /// Only `null` and `false` are falsy
+    pub fn truthy(&self) -> bool {
+        !matches!(self, Self::Null | Self::Bool(false))
+    }
+    /// Numeric value of ints and floats
+    pub fn number(&self) -> Option<f64> {
+        match self {
+            Self::Int(int) => Some(*int as f64),
+            Self::Float(float) => Some(*float),
+            _ => None,
+        }
+    }
+    /// Equality as the language sees it, ints and floats compare by their numeric value, also
+    /// inside lists and maps
+    pub fn equals(&self, other: &Self) -> bool {
+        self.eq_visiting(other, true, &mut vec![])
+    }
+    pub fn binary(&self, op: BinaryOperator, other: &Self) -> Result<Self, RuntimeError> {
+        let invalid = || RuntimeError::InvalidBinary(op, self.kind(), other.kind());
+        match op {
+            BinaryOperator::Eq => return Ok(Self::Bool(self.equals(other))),
+            BinaryOperator::Ne => return Ok(Self::Bool(!self.equals(other))),
+            _ => {}
+        }
+        match (self, other) {
+            (Self::Int(left), Self::Int(right)) => {
+                let (left, right) = (*left, *right);
+                let checked = match op {
+                    BinaryOperator::Add => left.checked_add(right),
+                    BinaryOperator::Sub => left.checked_sub(right),
+                    BinaryOperator::Mul => left.checked_mul(right),
+                    BinaryOperator::Div | BinaryOperator::Mod if right == 0 => {
+                        return Err(RuntimeError::DivisionByZero)
+                    }
+                    BinaryOperator::Div => left.checked_div(right),
+                    BinaryOperator::Mod => left.checked_rem(right),
+                    _ => return Ok(compare(op, left.cmp(&right))),
+                };
+                checked.map(Self::Int).ok_or(RuntimeError::Overflow)
+            }
+            (Self::Int(_) | Self::Float(_), Self::Int(_) | Self::Float(_)) => {
+                let (left, right) = (self.number().unwrap(), other.number().unwrap());
+                Ok(Self::Float(match op {
+                    BinaryOperator::Add => left + right,
+                    BinaryOperator::Sub => left - right,
+                    BinaryOperator::Mul => left * right,
+                    BinaryOperator::Div => left / right,
+                    BinaryOperator::Mod => left % right,
+                    _ => {
+                        return left
+                            .partial_cmp(&right)
+                            .map(|ord| compare(op, ord))
+                            .ok_or_else(invalid)
+                    }
+                }))
+            }
+            (Self::String(left), Self::String(right)) => match op {
+                BinaryOperator::Add => Ok(Self::String(format!("{left}{right}"))),
+                BinaryOperator::Lt
+                | BinaryOperator::Le
+                | BinaryOperator::Gt
+                | BinaryOperator::Ge => Ok(compare(op, left.cmp(right))),
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+    pub fn unary(&self, op: UnaryOperator) -> Result<Self, RuntimeError> {
+        match (op, self) {
+            (UnaryOperator::Neg, Self::Int(int)) => int
+                .checked_neg()
+                .map(Self::Int)
+                .ok_or(RuntimeError::Overflow),
+            (UnaryOperator::Neg, Self::Float(float)) => Ok(Self::Float(-float)),
+            (UnaryOperator::Not, value) => Ok(Self::Bool(!value.truthy())),
+            (op, value) => Err(RuntimeError::InvalidUnary(op, value.kind())),
+        }
+    }
+    /// `self[index]`, missing map keys read as `null`
+    pub fn index(&self, index: &Self) -> Result<Self, RuntimeError> {
+        match (self, index) {
+            (Self::List(list), Self::Int(idx)) => {
+                let list = list.borrow();
+                position(*idx, list.len()).map(|idx| list[idx].clone())
+            }
+            (Self::String(string), Self::Int(idx)) => {
+                let chars: Vec<char> = string.chars().collect();
+                position(*idx, chars.len()).map(|idx| Self::String(chars[idx].to_string()))
+            }
+            (Self::Map(map), Self::String(key)) => {
+                Ok(map.borrow().get(key).cloned().unwrap_or_default())
+            }
+            _ => Err(RuntimeError::InvalidIndex(self.kind(), index.kind())),
+        }
+    }
+    /// `self[index] = value`
+    pub fn set_index(&self, index: &Self, value: Self) -> Result<(), RuntimeError> {
+        match (self, index) {
+            (Self::List(list), Self::Int(idx)) => {
+                let mut list = list.borrow_mut();
+                let idx = position(*idx, list.len())?;
+                list[idx] = value;
+                Ok(())
+            }
+            (Self::Map(map), Self::String(key)) => {
+                map.borrow_mut().insert(key.clone(), value);
+                Ok(())
+            }
+            _ => Err(RuntimeError::InvalidIndex(self.kind(), index.kind())),
+        }
+    }
+    /// Structural equality, comparing ints and floats by their value if `numeric`. A pair of
+    /// lists or maps that is already being compared further up counts as equal, so cyclic values
+    /// compare without recursing forever
+    fn eq_visiting(&self, other: &Self, numeric: bool, visiting: &mut Vec<(usize, usize)>) -> bool {
+        match (self, other) {
+            (Self::Int(_), Self::Float(_)) | (Self::Float(_), Self::Int(_)) if numeric => {
+                self.number() == other.number()
+            }
+            (Self::Null, Self::Null) => true,
+            (Self::Int(left), Self::Int(right)) => left == right,
+            (Self::Float(left), Self::Float(right)) => left == right,
+            (Self::String(left), Self::String(right)) => left == right,
+            (Self::Bool(left), Self::Bool(right)) => left == right,
+            (Self::List(left), Self::List(right)) => {
+                let pair = (Rc::as_ptr(left) as usize, Rc::as_ptr(right) as usize);
+                if Rc::ptr_eq(left, right) || visiting.contains(&pair) {
+                    return true;
+                }
+                let (left, right) = (left.borrow(), right.borrow());
+                visiting.push(pair);
+                let equal = left.len() == right.len()
+                    && left
+                        .iter()
+                        .zip(right.iter())
+                        .all(|(left, right)| left.eq_visiting(right, numeric, visiting));
+                visiting.pop();
+                equal
+            }
+            (Self::Map(left), Self::Map(right)) => {
+                let pair = (Rc::as_ptr(left) as usize, Rc::as_ptr(right) as usize);
+                if Rc::ptr_eq(left, right) || visiting.contains(&pair) {
+                    return true;
+                }
+                let (left, right) = (left.borrow(), right.borrow());
+                visiting.push(pair);
+                let equal = left.len() == right.len()
+                    && left.iter().zip(right.iter()).all(|(left, right)| {
+                        left.0 == right.0 && left.1.eq_visiting(right.1, numeric, visiting)
+                    });
+                visiting.pop();
+                equal
+            }
+            (Self::Function(left), Self::Function(right)) => Rc::ptr_eq(left, right),
+            (Self::Native(left), Self::Native(right)) => Rc::ptr_eq(left, right),
+            _ => false,
+        }
+    }
+    /// Writes the value, `visiting` holds the lists and maps it is nested in
+    fn fmt_visiting(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        visiting: &mut Vec<usize>,
+        nested: bool,
+    ) -> std::fmt::Result {
+        match self {
+            Self::Null => write!(f, "null"),
+            Self::Int(int) => write!(f, "{int}"),
+            Self::Float(float) => write!(f, "{float:?}"),
+            Self::String(string) if nested => write!(f, "{string:?}"),
+            Self::String(string) => write!(f, "{string}"),
+            Self::Bool(value) => write!(f, "{value}"),
+            Self::List(list) if visiting.contains(&(Rc::as_ptr(list) as usize)) => {
+                write!(f, "[...]")
+            }
+            Self::List(list) => {
+                visiting.push(Rc::as_ptr(list) as usize);
+                write!(f, "[")?;
+                for (idx, item) in list.borrow().iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ", ")?;
+                    }
+                    item.fmt_visiting(f, visiting, true)?;
+                }
+                visiting.pop();
+                write!(f, "]")
+            }
+            Self::Map(map) if visiting.contains(&(Rc::as_ptr(map) as usize)) => {
+                write!(f, "{{...}}")
+            }
+            Self::Map(map) if map.borrow().is_empty() => write!(f, "{{}}"),
+            Self::Map(map) => {
+                visiting.push(Rc::as_ptr(map) as usize);
+                write!(f, "{{ ")?;
+                for (key, value) in map.borrow().iter() {
+                    write!(f, "{key:?} = ")?;
+                    value.fmt_visiting(f, visiting, true)?;
+                    write!(f, "; ")?;
+                }
+                visiting.pop();
+                write!(f, "}}")
+            }
+            Self::Function(_) => write!(f, "<function>"),
+            Self::Native(native) => write!(f, "<native {}>", native.name),
+        }
+    }
+}
+
+fn compare(op: BinaryOperator, ordering: Ordering) -> Value {
+    Value::Bool(match op {
+        BinaryOperator::Lt => ordering.is_lt(),
+        BinaryOperator::Le => ordering.is_le(),
+        BinaryOperator::Gt => ordering.is_gt(),
+        BinaryOperator::Ge => ordering.is_ge(),
+        _ => unreachable!("not a comparison operator"),
+    })
+}
+fn position(idx: i64, len: usize) -> Result<usize, RuntimeError> {
+    usize::try_from(idx)
+        .ok()
+        .filter(|&idx| idx < len)
+        .ok_or(RuntimeError::IndexOutOfRange(idx, len))
+}
@@ -1,6 +1,18 @@
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt::Display,
+    sync::Arc,
+};
 
-use crate::position::Located;
+use crate::{
+    parser::{BinaryOperator, UnaryOperator},
+    position::{Located, Position},
+    CancelToken,
+};
+
+pub mod cfg;
+pub mod optimize;
 
 #[derive(Debug, Clone, PartialEq, Default)]
 pub enum IR {
@@ -16,12 +28,37 @@ pub enum IR {
         addr: usize,
     },
 
+    /// Creates an iterator over the elements of a list or the keys of a map
+    IterInit {
+        dst: usize,
+        src: usize,
+    },
+    /// Writes the iterator's next value to `dst`, jumping to `addr` once it is exhausted
+    IterNext {
+        dst: usize,
+        iter: usize,
+        addr: usize,
+    },
+
     Call {
         dst: Option<usize>,
         func: usize,
         start: usize,
         amount: usize,
     },
+    /// Like `Call`, with the arguments taken from the list in `args`
+    CallSpread {
+        dst: Option<usize>,
+        func: usize,
+        args: usize,
+    },
+    Return {
+        src: Option<usize>,
+    },
+    Closure {
+        dst: usize,
+        addr: usize,
+    },
 
     Move {
         dst: usize,
@@ -48,6 +85,13 @@ pub enum IR {
         dst: usize,
         addr: usize,
     },
+    Bool {
+        dst: usize,
+        value: bool,
+    },
+    Null {
+        dst: usize,
+    },
 
     List {
         dst: usize,
@@ -56,6 +100,11 @@ pub enum IR {
     Map {
         dst: usize,
     },
+    /// Appends the elements of the list in `src` to the list in `dst`
+    Extend {
+        dst: usize,
+        src: usize,
+    },
 
     Field {
         dst: usize,
@@ -67,6 +116,121 @@ pub enum IR {
         head: usize,
         addr: usize,
     },
+    SetField {
+        head: usize,
+        field: usize,
+        src: usize,
+    },
+    SetFieldString {
+        head: usize,
+        addr: usize,
+        src: usize,
+    },
+    Index {
+        dst: usize,
+        head: usize,
+        index: usize,
+    },
+    SetIndex {
+        head: usize,
+        index: usize,
+        src: usize,
+    },
+
+    Binary {
+        op: BinaryOperator,
+        dst: usize,
+        left: usize,
+        right: usize,
+    },
+    Unary {
+        op: UnaryOperator,
+        dst: usize,
+        src: usize,
+    },
+}
+impl IR {
+    /// The jump target of control flow instructions, a label id until labels are resolved
+    pub fn target(&self) -> Option<usize> {
+        match self {
+            Self::Jump { addr } | Self::JumpIf { addr, .. } | Self::IterNext { addr, .. } => {
+                Some(*addr)
+            }
+            _ => None,
+        }
+    }
+    pub fn target_mut(&mut self) -> Option<&mut usize> {
+        match self {
+            Self::Jump { addr } | Self::JumpIf { addr, .. } | Self::IterNext { addr, .. } => {
+                Some(addr)
+            }
+            _ => None,
+        }
+    }
+    /// Registers the instruction reads
+    pub fn reads(&self) -> Vec<usize> {
+        match self {
+            Self::None
+            | Self::Jump { .. }
+            | Self::Closure { .. }
+            | Self::Get { .. }
+            | Self::String { .. }
+            | Self::Int { .. }
+            | Self::Float { .. }
+            | Self::Bool { .. }
+            | Self::Null { .. }
+            | Self::Map { .. } => vec![],
+            Self::JumpIf { cond, .. } => vec![*cond],
+            Self::IterInit { src, .. }
+            | Self::Move { src, .. }
+            | Self::Set { src, .. }
+            | Self::Unary { src, .. } => vec![*src],
+            Self::IterNext { iter, .. } => vec![*iter],
+            Self::Call {
+                func,
+                start,
+                amount,
+                ..
+            } => std::iter::once(*func)
+                .chain(*start..*start + *amount)
+                .collect(),
+            Self::CallSpread { func, args, .. } => vec![*func, *args],
+            Self::Return { src } => src.iter().copied().collect(),
+            Self::List { dst, length } => (*dst + 1..=*dst + *length).collect(),
+            Self::Extend { dst, src } => vec![*dst, *src],
+            Self::Field { head, field, .. } => vec![*head, *field],
+            Self::FieldString { head, .. } => vec![*head],
+            Self::SetField { head, field, src } => vec![*head, *field, *src],
+            Self::SetFieldString { head, src, .. } => vec![*head, *src],
+            Self::Index { head, index, .. } => vec![*head, *index],
+            Self::SetIndex { head, index, src } => vec![*head, *index, *src],
+            Self::Binary { left, right, .. } => vec![*left, *right],
+        }
+    }
+    /// Register the instruction writes its result to
+    pub fn writes(&self) -> Option<usize> {
+        match self {
+            Self::IterInit { dst, .. }
+            | Self::IterNext { dst, .. }
+            | Self::Closure { dst, .. }
+            | Self::Move { dst, .. }
+            | Self::Get { dst, .. }
+            | Self::String { dst, .. }
+            | Self::Int { dst, .. }
+            | Self::Float { dst, .. }
+            | Self::Bool { dst, .. }
+            | Self::Null { dst }
+            | Self::List { dst, .. }
+            | Self::Map { dst }
+            | Self::Field { dst, .. }
+            | Self::FieldString { dst, .. }
+            | Self::Index { dst, .. }
+            | Self::Binary { dst, .. }
+            | Self::Unary { dst, .. } => Some(*dst),
+            Self::Call { dst, .. } | Self::CallSpread { dst, .. } => *dst,
+            _ => None,
+        }
+    }
 }
 #[derive(Debug, Clone, PartialEq, Default)]
 pub struct LabeledIR {
@@ -88,12 +252,93 @@ pub struct Closure {
     pub string: Vec<String>,
     pub int: Vec<i64>,
     pub float: Vec<f64>,
+    /// Nested closures, shared so running the program never copies their code
+    pub closures: Vec<Arc<Closure>>,
+    /// Number of parameters, passed in the first registers
+    pub params: usize,
+    /// Number of registers the closure uses at most
+    pub registers: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Scope {
+    pub locals: HashMap<String, usize>,
+    /// Registers in use when the scope was opened, the ones allocated since are freed with it
+    pub registers: HashSet<usize>,
+    /// Whether assigning to a name that is not visible declares a local instead of a global, for
+    /// the scope and the ones nested in it
+    pub declaring: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkError {
+    UndefinedLabel(usize),
+}
+impl Display for LinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UndefinedLabel(label) => write!(f, "jump to undefined label {label}"),
+        }
+    }
+}
+impl Error for LinkError {}
+impl Closure {
+    /// Source position of the instruction at `addr`
+    pub fn position(&self, addr: usize) -> Option<&Position> {
+        self.code.get(addr).map(|ir| &ir.pos)
+    }
+    /// Rewrites jump targets from label ids to instruction indices, removing the label markers.
+    /// Nested closures are resolved as well.
+    pub fn resolve_labels(&mut self) -> Result<(), Located<LinkError>> {
+        let mut labels = HashMap::new();
+        let mut code = Vec::with_capacity(self.code.len());
+        for Located { value: ir, pos } in self.code.iter() {
+            if let Some(label) = ir.label {
+                labels.insert(label, code.len());
+                if ir.ir == IR::None {
+                    continue;
+                }
+            }
+            code.push(Located::new(LabeledIR::new(ir.ir.clone()), pos.clone()));
+        }
+        for ir in code.iter_mut() {
+            if let Some(addr) = ir.value.ir.target_mut() {
+                *addr = *labels.get(addr).ok_or_else(|| {
+                    Located::new(LinkError::UndefinedLabel(*addr), ir.pos.clone())
+                })?;
+            }
+        }
+        self.code = code;
+        for closure in self.closures.iter_mut() {
+            Arc::make_mut(closure).resolve_labels()?;
+        }
+        Ok(())
+    }
+}
+
+/// Indices of the constants already in a closure, floats are keyed by their bits
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Constants {
+    pub strings: HashMap<String, usize>,
+    pub ints: HashMap<i64, usize>,
+    pub floats: HashMap<u64, usize>,
 }
 
 pub struct IRCompiler {
     pub closure_stack: Vec<Closure>,
     pub registers: Vec<HashSet<usize>>,
     pub labels: Vec<Vec<usize>>,
+    /// `(continue, break)` labels of the enclosing loops, innermost last
+    pub loops: Vec<Vec<(usize, usize)>>,
+    /// Local variables and their registers, innermost scope last
+    pub scopes: Vec<Vec<Scope>>,
+    pub constants: Vec<Constants>,
+    pub cancel: Option<CancelToken>,
+}
+impl Default for IRCompiler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 impl IRCompiler {
     pub fn new() -> Self {
@@ -101,16 +346,36 @@ impl IRCompiler {
             closure_stack: vec![Closure::default()],
             registers: vec![HashSet::default()],
             labels: vec![vec![]],
+            loops: vec![vec![]],
+            scopes: vec![vec![]],
+            constants: vec![Constants::default()],
+            cancel: None,
         }
     }
+    pub fn with_cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(CancelToken::is_cancelled)
+    }
     pub fn push_closure(&mut self) {
         self.closure_stack.push(Closure::default());
         self.registers.push(HashSet::default());
         self.labels.push(vec![]);
+        self.loops.push(vec![]);
+        self.scopes.push(vec![Scope {
+            declaring: true,
+            ..Scope::default()
+        }]);
+        self.constants.push(Constants::default());
     }
     pub fn pop_closure(&mut self) -> Option<Closure> {
         self.registers.pop();
         self.labels.pop();
+        self.loops.pop();
+        self.scopes.pop();
+        self.constants.pop();
         self.closure_stack.pop()
     }
     pub fn closure(&self) -> Option<&Closure> {
@@ -131,4 +396,174 @@ impl IRCompiler {
     pub fn labels_mut(&mut self) -> Option<&mut Vec<usize>> {
         self.labels.last_mut()
     }
+    pub fn loops(&self) -> Option<&Vec<(usize, usize)>> {
+        self.loops.last()
+    }
+    pub fn loops_mut(&mut self) -> Option<&mut Vec<(usize, usize)>> {
+        self.loops.last_mut()
+    }
+    /// Opens a scope, `declaring` new names assigned in it as its locals
+    pub fn push_scope(&mut self, declaring: bool) {
+        let registers = self.registers().cloned().unwrap_or_default();
+        if let Some(scopes) = self.scopes.last_mut() {
+            scopes.push(Scope {
+                locals: HashMap::default(),
+                registers,
+                declaring,
+            });
+        }
+    }
+    /// Closes the innermost scope, freeing the registers allocated since it was opened
+    pub fn pop_scope(&mut self) -> Option<Scope> {
+        let scope = self.scopes.last_mut()?.pop()?;
+        self.free_registers_since(&scope.registers);
+        Some(scope)
+    }
+    /// Number of scopes open in the current closure
+    pub fn scope_depth(&self) -> usize {
+        self.scopes.last().map_or(0, Vec::len)
+    }
+    /// Binds `name` to `register` in the innermost scope of the current closure
+    pub fn declare(&mut self, name: String, register: usize) {
+        if let Some(scope) = self.scopes.last_mut().and_then(|scopes| scopes.last_mut()) {
+            scope.locals.insert(name, register);
+        }
+    }
+    /// Binds `name` to `register` in the innermost declaring scope of the current closure, so the
+    /// local outlives the branches and loop bodies it is first assigned in
+    pub fn declare_local(&mut self, name: String, register: usize) {
+        if let Some(scope) = self
+            .scopes
+            .last_mut()
+            .and_then(|scopes| scopes.iter_mut().rev().find(|scope| scope.declaring))
+        {
+            scope.locals.insert(name, register);
+        }
+    }
+    /// Whether a new name assigned to in the current scope is declared as a local
+    pub fn declaring(&self) -> bool {
+        self.scopes
+            .last()
+            .is_some_and(|scopes| scopes.iter().any(|scope| scope.declaring))
+    }
+    /// Register of the local variable `name` visible in the current closure
+    pub fn local(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .last()?
+            .iter()
+            .rev()
+            .find_map(|scope| scope.locals.get(name).copied())
+    }
+    /// Whether `name` is a local variable of a closure enclosing the current one
+    pub fn enclosing_local(&self, name: &str) -> bool {
+        let enclosing = self.scopes.len().saturating_sub(1);
+        self.scopes[..enclosing]
+            .iter()
+            .flatten()
+            .any(|scope| scope.locals.contains_key(name))
+    }
+    /// Appends an instruction to the current closure, returning its address
+    pub fn write(&mut self, ir: IR, pos: Position) -> usize {
+        let code = &mut self.closure_mut().expect("closure stack is empty").code;
+        code.push(Located::new(LabeledIR::new(ir), pos));
+        code.len() - 1
+    }
+    pub fn alloc_register(&mut self) -> usize {
+        self.alloc_registers(1)
+    }
+    /// Reserves `amount` consecutive registers at the lowest free slots, returning the first one
+    pub fn alloc_registers(&mut self, amount: usize) -> usize {
+        let registers = self.cregisters_mut().expect("closure stack is empty");
+        let mut start = 0;
+        while let Some(taken) =
+            (start..start + amount.max(1)).find(|register| registers.contains(register))
+        {
+            start = taken + 1;
+        }
+        registers.extend(start..start + amount);
+        let closure = self.closure_mut().expect("closure stack is empty");
+        closure.registers = closure.registers.max(start + amount);
+        start
+    }
+    pub fn free_register(&mut self, register: usize) {
+        if let Some(registers) = self.cregisters_mut() {
+            registers.remove(&register);
+        }
+    }
+    /// Frees every register allocated since `registers` were the ones in use, except those of
+    /// local variables still in scope
+    pub fn free_registers_since(&mut self, registers: &HashSet<usize>) {
+        let locals: HashSet<usize> = self
+            .scopes
+            .last()
+            .into_iter()
+            .flatten()
+            .flat_map(|scope| scope.locals.values().copied())
+            .collect();
+        if let Some(current) = self.cregisters_mut() {
+            current.retain(|register| registers.contains(register) || locals.contains(register));
+        }
+    }
+    /// Runs `f` with a register that is freed again afterwards
+    pub fn with_temp<T>(&mut self, f: impl FnOnce(&mut Self, usize) -> T) -> T {
+        let register = self.alloc_register();
+        let result = f(self, register);
+        self.free_register(register);
+        result
+    }
+    /// Creates a label that jumps can target before it is placed with `set_label`
+    pub fn new_label(&mut self) -> usize {
+        let labels = self.labels_mut().expect("closure stack is empty");
+        labels.push(usize::MAX);
+        labels.len() - 1
+    }
+    /// Places `label` at the next instruction written
+    pub fn set_label(&mut self, label: usize, pos: Position) {
+        let closure = self.closure_mut().expect("closure stack is empty");
+        closure
+            .code
+            .push(Located::new(LabeledIR::new(IR::None).labeled(label), pos));
+        let addr = closure.code.len() - 1;
+        self.labels_mut().expect("closure stack is empty")[label] = addr;
+    }
+    /// Index of `string` in the current closure's string constants, adding it if it is new
+    pub fn add_string(&mut self, string: String) -> usize {
+        let constants = self.constants.last_mut().expect("closure stack is empty");
+        let strings = &mut self
+            .closure_stack
+            .last_mut()
+            .expect("closure stack is empty")
+            .string;
+        *constants
+            .strings
+            .entry(string)
+            .or_insert_with_key(|string| {
+                strings.push(string.clone());
+                strings.len() - 1
+            })
+    }
+    pub fn add_int(&mut self, int: i64) -> usize {
+        let constants = self.constants.last_mut().expect("closure stack is empty");
+        let ints = &mut self
+            .closure_stack
+            .last_mut()
+            .expect("closure stack is empty")
+            .int;
+        *constants.ints.entry(int).or_insert_with(|| {
+            ints.push(int);
+            ints.len() - 1
+        })
+    }
+    pub fn add_float(&mut self, float: f64) -> usize {
+        let constants = self.constants.last_mut().expect("closure stack is empty");
+        let floats = &mut self
+            .closure_stack
+            .last_mut()
+            .expect("closure stack is empty")
+            .float;
+        *constants.floats.entry(float.to_bits()).or_insert_with(|| {
+            floats.push(float);
+            floats.len() - 1
+        })
+    }
 }
@@ -0,0 +1,96 @@
+use std::{collections::HashMap, ops::Range};
+
+use super::{Closure, IR};
+
+/// Straight line run of instructions, only entered at its start and only left at its end
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Block {
+    /// Instruction indices in the closure's code
+    pub code: Range<usize>,
+    /// Indices of the blocks control may continue to
+    pub successors: Vec<usize>,
+}
+
+/// Control-flow graph of a closure with unresolved labels
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Cfg {
+    pub blocks: Vec<Block>,
+}
+impl Cfg {
+    pub fn new(closure: &Closure) -> Self {
+        let code = &closure.code;
+        let mut leaders = vec![0];
+        for (idx, ir) in code.iter().enumerate() {
+            if ir.value.label.is_some() {
+                leaders.push(idx);
+            }
+            if ir.value.ir.target().is_some() || matches!(ir.value.ir, IR::Return { .. }) {
+                leaders.push(idx + 1);
+            }
+        }
+        leaders.retain(|&idx| idx < code.len());
+        leaders.sort_unstable();
+        leaders.dedup();
+
+        let mut labels = HashMap::new();
+        for (block, &start) in leaders.iter().enumerate() {
+            if let Some(label) = code[start].value.label {
+                labels.insert(label, block);
+            }
+        }
+        let blocks = leaders
+            .iter()
+            .enumerate()
+            .map(|(block, &start)| {
+                let end = leaders.get(block + 1).copied().unwrap_or(code.len());
+                let last = &code[end - 1].value.ir;
+                let mut successors = vec![];
+                if !matches!(last, IR::Jump { .. } | IR::Return { .. }) && end < code.len() {
+                    successors.push(block + 1);
+                }
+                if let Some(&target) = last.target().and_then(|addr| labels.get(&addr)) {
+                    if !successors.contains(&target) {
+                        successors.push(target);
+                    }
+                }
+                Block {
+                    code: start..end,
+                    successors,
+                }
+            })
+            .collect();
+        Self { blocks }
+    }
+    /// Whether each block can be reached from the entry block
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.blocks.len()];
+        let mut stack = vec![0];
+        while let Some(block) = stack.pop() {
+            if block >= self.blocks.len() || reachable[block] {
+                continue;
+            }
+            reachable[block] = true;
+            stack.extend(self.blocks[block].successors.iter().copied());
+        }
+        reachable
+    }
+}
+
+/// Removes blocks that can never be reached. Jump targets are still label ids at this point, so
+/// they stay valid as long as the labels of reachable blocks are kept, which `resolve_labels`
+/// then maps onto the shifted instruction indices
+pub fn remove_unreachable(closure: &mut Closure) {
+    let cfg = Cfg::new(closure);
+    let reachable = cfg.reachable();
+    let mut keep = vec![false; closure.code.len()];
+    for (block, _) in cfg
+        .blocks
+        .iter()
+        .zip(reachable)
+        .filter(|(_, reachable)| *reachable)
+    {
+        keep[block.code.clone()].fill(true);
+    }
+    let mut keep = keep.into_iter();
+    closure.code.retain(|_| keep.next().unwrap_or(true));
+}
@@ -0,0 +1,122 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use super::{cfg, Closure, LabeledIR, IR};
+use crate::position::Located;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum OptLevel {
+    #[default]
+    None,
+    /// Peephole pass over each closure
+    Peephole,
+    /// Unreachable code removal followed by the peephole pass
+    Full,
+}
+
+/// Optimizes `closure` and its nested closures in place. Expects unresolved labels.
+pub fn optimize(closure: &mut Closure, level: OptLevel) {
+    if level == OptLevel::None {
+        return;
+    }
+    if level >= OptLevel::Full {
+        cfg::remove_unreachable(closure);
+    }
+    peephole(closure);
+    for closure in closure.closures.iter_mut() {
+        optimize(Arc::make_mut(closure), level);
+    }
+}
+
+/// Removes self moves, collapses jump chains and drops pure instructions whose result is never read
+pub fn peephole(closure: &mut Closure) {
+    closure
+        .code
+        .retain(|ir| !matches!(ir.value.ir, IR::Move { dst, src } if dst == src));
+    collapse_jumps(closure);
+    remove_dead_writes(closure);
+}
+
+/// Retargets jumps to a label followed by an unconditional jump to that jump's target
+fn collapse_jumps(closure: &mut Closure) {
+    let mut forwards = HashMap::new();
+    for (idx, ir) in closure.code.iter().enumerate() {
+        let Some(label) = ir.value.label else {
+            continue;
+        };
+        let next = closure.code[idx..]
+            .iter()
+            .find(|ir| !(ir.value.ir == IR::None && ir.value.label.is_some()));
+        if let Some(IR::Jump { addr }) = next.map(|ir| &ir.value.ir) {
+            forwards.insert(label, *addr);
+        }
+    }
+    for ir in closure.code.iter_mut() {
+        let Some(addr) = ir.value.ir.target_mut() else {
+            continue;
+        };
+        let mut visited = HashSet::from([*addr]);
+        while let Some(&next) = forwards.get(addr) {
+            if !visited.insert(next) {
+                break;
+            }
+            *addr = next;
+        }
+    }
+}
+
+/// Repeatedly removes side effect free instructions whose result is never read, either
+/// anywhere in the closure or before being overwritten in the same basic block. `Get` can fail
+/// and lists and maps count against the allocation limit, so those are kept.
+fn remove_dead_writes(closure: &mut Closure) {
+    loop {
+        let read: HashSet<usize> = closure
+            .code
+            .iter()
+            .flat_map(|ir| ir.value.ir.reads())
+            .collect();
+        let dead: HashSet<usize> = closure
+            .code
+            .iter()
+            .enumerate()
+            .filter(|(idx, ir)| {
+                let pure = matches!(
+                    ir.value.ir,
+                    IR::Move { .. }
+                        | IR::String { .. }
+                        | IR::Int { .. }
+                        | IR::Float { .. }
+                        | IR::Bool { .. }
+                        | IR::Null { .. }
+                        | IR::Closure { .. }
+                );
+                let Some(dst) = ir.value.ir.writes().filter(|_| pure) else {
+                    return false;
+                };
+                !read.contains(&dst) || overwritten(&closure.code[idx + 1..], dst)
+            })
+            .map(|(idx, _)| idx)
+            .collect();
+        if dead.is_empty() {
+            break;
+        }
+        let mut idx = 0;
+        closure.code.retain(|_| {
+            idx += 1;
+            !dead.contains(&(idx - 1))
+        });
+    }
+}
+
+/// Whether `register` is written before being read, without leaving the basic block
+fn overwritten(code: &[Located<LabeledIR>], register: usize) -> bool {
+    code.iter()
+        .map(|ir| &ir.value)
+        .take_while(|ir| {
+            ir.label.is_none() && ir.ir.target().is_none() && !matches!(ir.ir, IR::Return { .. })
+        })
+        .find(|ir| ir.ir.reads().contains(&register) || ir.ir.writes() == Some(register))
+        .is_some_and(|ir| !ir.ir.reads().contains(&register))
+}
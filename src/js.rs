@@ -0,0 +1,390 @@
+use std::collections::HashSet;
+
+use crate::parser::{
+    Argument, Atom, BinaryOperator, Expression, Path, Program, Statement, UnaryOperator,
+};
+
+/// Translation of the AST into JavaScript source
+pub trait ToJs {
+    fn to_js(&self, indent: usize) -> String;
+}
+
+pub fn transpile(program: &Program) -> String {
+    program.to_js(0)
+}
+
+const RESERVED: &[&str] = &[
+    "await", "break", "case", "catch", "class", "const", "continue", "debugger", "default",
+    "delete", "do", "else", "enum", "export", "extends", "false", "finally", "for", "function",
+    "if", "import", "in", "instanceof", "let", "new", "null", "return", "super", "switch", "this",
+    "throw", "true", "try", "typeof", "var", "void", "while", "with", "yield",
+];
+
+fn ident(name: &str) -> String {
+    if RESERVED.contains(&name) || name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{name}")
+    } else {
+        name.to_string()
+    }
+}
+fn string(value: &str) -> String {
+    let mut js = String::from('"');
+    for c in value.chars() {
+        match c {
+            '"' => js.push_str("\\\""),
+            '\\' => js.push_str("\\\\"),
+            '\n' => js.push_str("\\n"),
+            '\t' => js.push_str("\\t"),
+            '\r' => js.push_str("\\r"),
+            c if c.is_control() => js.push_str(&format!("\\u{:04x}", c as u32)),
+            c => js.push(c),
+        }
+    }
+    js.push('"');
+    js
+}
+fn list<T: ToJs>(values: impl IntoIterator<Item = T>, indent: usize) -> String {
+    values
+        .into_iter()
+        .map(|value| value.to_js(indent))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+/// Positional arguments followed by an object of the keyword arguments, matching the IR
+fn arguments(args: &[Argument], indent: usize) -> String {
+    let mut js: Vec<String> = args
+        .iter()
+        .filter(|(name, _)| name.is_none())
+        .map(|(_, arg)| arg.to_js(indent))
+        .collect();
+    let keywords: Vec<String> = args
+        .iter()
+        .filter_map(|(name, arg)| {
+            let name = name.as_ref()?;
+            Some(format!("{}: {}", string(&name.value), arg.to_js(indent)))
+        })
+        .collect();
+    if !keywords.is_empty() {
+        js.push(format!("{{{}}}", keywords.join(", ")));
+    }
+    js.join(", ")
+}
+
+fn field(head: &str, field: &crate::position::Located<Atom>, indent: usize) -> String {
+    match &field.value {
+        Atom::Path(Path::Ident(name)) if !RESERVED.contains(&name.as_str()) => {
+            format!("{head}.{name}")
+        }
+        Atom::Path(Path::Ident(name)) => format!("{head}[{}]", string(name)),
+        atom => format!("{head}[{}]", atom.to_js(indent)),
+    }
+}
+
+/// Local variables of the function being translated, mirroring the compiler's scopes
+#[derive(Debug, Clone, Default)]
+struct Locals {
+    names: HashSet<String>,
+    /// Whether assigning to a name that is not a local declares one, see `compiler::assign`
+    declaring: bool,
+}
+fn block(stats: &[crate::position::Located<Statement>], indent: usize, locals: &Locals) -> String {
+    block_declaring(stats, indent, locals, &[])
+}
+/// Translates the body of a function or a `{}` block. The names first assigned in it, also in
+/// nested branches and loops, are declared with `let` up front, as the compiler keeps them in
+/// the function or block scope rather than in the branch they are assigned in.
+fn scope(stats: &[crate::position::Located<Statement>], indent: usize, locals: &Locals) -> String {
+    let mut declared = vec![];
+    assigned(stats, &locals.names, &mut declared);
+    let mut locals = locals.clone();
+    locals.names.extend(declared.iter().cloned());
+    locals.declaring = true;
+    block_declaring(stats, indent, &locals, &declared)
+}
+fn block_declaring(
+    stats: &[crate::position::Located<Statement>],
+    indent: usize,
+    locals: &Locals,
+    declared: &[String],
+) -> String {
+    let mut js = String::from("{\n");
+    if !declared.is_empty() {
+        let names: Vec<String> = declared.iter().map(|name| ident(name)).collect();
+        js.push_str(&"    ".repeat(indent + 1));
+        js.push_str(&format!("let {};\n", names.join(", ")));
+    }
+    for stat in stats {
+        js.push_str(&"    ".repeat(indent + 1));
+        js.push_str(&statement(&stat.value, indent + 1, locals));
+        js.push('\n');
+    }
+    js.push_str(&"    ".repeat(indent));
+    js.push('}');
+    js
+}
+/// Pushes the names assigned in `stats` that are not in `locals` yet, in order of their first
+/// assignment. Blocks and functions are scopes of their own and are skipped.
+fn assigned(
+    stats: &[crate::position::Located<Statement>],
+    locals: &HashSet<String>,
+    names: &mut Vec<String>,
+) {
+    for stat in stats {
+        let name = match &stat.value {
+            Statement::Assign { path, expr: _ } => match &path.value {
+                Path::Ident(name) => name,
+                _ => continue,
+            },
+            Statement::Function { name, .. } => &name.value,
+            Statement::If {
+                cond: _,
+                case,
+                else_case,
+            } => {
+                assigned(case, locals, names);
+                if let Some(else_case) = else_case {
+                    assigned(else_case, locals, names);
+                }
+                continue;
+            }
+            Statement::While { cond: _, body } => {
+                assigned(body, locals, names);
+                continue;
+            }
+            Statement::For { var, iter: _, body } => {
+                let mut locals = locals.clone();
+                locals.insert(var.value.clone());
+                assigned(body, &locals, names);
+                continue;
+            }
+            _ => continue,
+        };
+        if !locals.contains(name) && !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+}
+/// Parameter list and body of a function, whose locals start out as its parameters
+fn function(
+    params: &[crate::position::Located<String>],
+    body: &[crate::position::Located<Statement>],
+    indent: usize,
+) -> String {
+    let locals = Locals {
+        names: params.iter().map(|param| param.value.clone()).collect(),
+        declaring: true,
+    };
+    format!(
+        "({}) {}",
+        params
+            .iter()
+            .map(|param| ident(&param.value))
+            .collect::<Vec<String>>()
+            .join(", "),
+        scope(body, indent, &locals)
+    )
+}
+
+impl<T: ToJs> ToJs for &T {
+    fn to_js(&self, indent: usize) -> String {
+        (*self).to_js(indent)
+    }
+}
+impl<T: ToJs> ToJs for crate::position::Located<T> {
+    fn to_js(&self, indent: usize) -> String {
+        self.value.to_js(indent)
+    }
+}
+/// A program returning at its top level is wrapped in a function that is called right away, as
+/// JavaScript only allows `return` in functions
+impl ToJs for Program {
+    fn to_js(&self, indent: usize) -> String {
+        if !returns(&self.0) {
+            return self
+                .0
+                .iter()
+                .map(|stat| format!("{}{}\n", "    ".repeat(indent), stat.to_js(indent)))
+                .collect();
+        }
+        let mut js = format!("{}(function() {{\n", "    ".repeat(indent));
+        for stat in &self.0 {
+            js.push_str(&format!(
+                "{}{}\n",
+                "    ".repeat(indent + 1),
+                stat.to_js(indent + 1)
+            ));
+        }
+        js.push_str(&format!("{}}})();\n", "    ".repeat(indent)));
+        js
+    }
+}
+/// Whether `stats` contain a `return` outside of the functions defined in them
+fn returns(stats: &[crate::position::Located<Statement>]) -> bool {
+    stats.iter().any(|stat| match &stat.value {
+        Statement::Return(_) => true,
+        Statement::If {
+            cond: _,
+            case,
+            else_case,
+        } => returns(case) || else_case.as_deref().is_some_and(returns),
+        Statement::While { cond: _, body }
+        | Statement::For { body, .. }
+        | Statement::Block(body) => returns(body),
+        _ => false,
+    })
+}
+/// Statements outside of functions and blocks, where assigned names are globals
+impl ToJs for Statement {
+    fn to_js(&self, indent: usize) -> String {
+        statement(self, indent, &Locals::default())
+    }
+}
+fn statement(stat: &Statement, indent: usize, locals: &Locals) -> String {
+    match stat {
+        Statement::Assign { path, expr } => {
+            format!("{} = {};", path.to_js(indent), expr.to_js(indent))
+        }
+        Statement::Call { head, args } => {
+            format!("{}({});", head.to_js(indent), arguments(args, indent))
+        }
+        Statement::If {
+            cond,
+            case,
+            else_case,
+        } => {
+            let mut js = format!(
+                "if ({}) {}",
+                cond.to_js(indent),
+                block(case, indent, locals)
+            );
+            match else_case.as_deref() {
+                Some([stat]) if matches!(stat.value, Statement::If { .. }) => {
+                    js.push_str(" else ");
+                    js.push_str(&statement(&stat.value, indent, locals));
+                }
+                Some(stats) => {
+                    js.push_str(" else ");
+                    js.push_str(&block(stats, indent, locals));
+                }
+                None => {}
+            }
+            js
+        }
+        Statement::While { cond, body } => {
+            format!(
+                "while ({}) {}",
+                cond.to_js(indent),
+                block(body, indent, locals)
+            )
+        }
+        Statement::For { var, iter, body } => {
+            let mut locals = locals.clone();
+            locals.names.insert(var.value.clone());
+            format!(
+                "for (let {} of {}) {}",
+                ident(&var.value),
+                iter.to_js(indent),
+                block(body, indent, &locals)
+            )
+        }
+        // a local is already declared with `let`, so the function is assigned to it
+        Statement::Function { name, params, body } if locals.declaring => format!(
+            "{} = function{};",
+            ident(&name.value),
+            function(params, body, indent)
+        ),
+        Statement::Function { name, params, body } => format!(
+            "function {}{}",
+            ident(&name.value),
+            function(params, body, indent)
+        ),
+        Statement::Break => "break;".to_string(),
+        Statement::Continue => "continue;".to_string(),
+        Statement::Return(Some(expr)) => format!("return {};", expr.to_js(indent)),
+        Statement::Return(None) => "return;".to_string(),
+        Statement::Block(stats) => scope(stats, indent, locals),
+    }
+}
+impl ToJs for Expression {
+    fn to_js(&self, indent: usize) -> String {
+        match self {
+            Self::Atom(atom) => atom.to_js(indent),
+            Self::Call { head, args } => {
+                format!("{}({})", head.to_js(indent), arguments(args, indent))
+            }
+            Self::Binary { op, left, right } => format!(
+                "({} {} {})",
+                left.to_js(indent),
+                op.to_js(indent),
+                right.to_js(indent)
+            ),
+            Self::Unary { op, expr } => format!(
+                "({}{})",
+                match op {
+                    UnaryOperator::Neg => "-",
+                    UnaryOperator::Not => "!",
+                },
+                expr.to_js(indent)
+            ),
+            Self::Function { params, body } => {
+                format!("(function{})", function(params, body, indent))
+            }
+            Self::Field { head, field: atom } => field(&head.to_js(indent), atom, indent),
+            Self::Index { head, index } => {
+                format!("{}[{}]", head.to_js(indent), index.to_js(indent))
+            }
+            Self::Spread(expr) => format!("...{}", expr.to_js(indent)),
+        }
+    }
+}
+impl ToJs for BinaryOperator {
+    fn to_js(&self, _: usize) -> String {
+        match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Mod => "%",
+            Self::Eq => "===",
+            Self::Ne => "!==",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+        }
+        .to_string()
+    }
+}
+impl ToJs for Atom {
+    fn to_js(&self, indent: usize) -> String {
+        match self {
+            Self::Path(path) => path.to_js(indent),
+            Self::Integer(value) => value.to_string(),
+            Self::Decimal(value) => format!("{value:?}"),
+            Self::String(value) => string(value),
+            Self::Bool(value) => value.to_string(),
+            Self::Null => "null".to_string(),
+            Self::Expression(expr) => format!("({})", expr.to_js(indent)),
+            Self::List(exprs) => format!("[{}]", list(exprs, indent)),
+            Self::Map(pairs) => format!(
+                "{{{}}}",
+                pairs
+                    .iter()
+                    .map(|(key, expr)| format!("{}: {}", string(&key.value), expr.to_js(indent)))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+impl ToJs for Path {
+    fn to_js(&self, indent: usize) -> String {
+        match self {
+            Self::Ident(name) => ident(name),
+            Self::Field { head, field: atom } => field(&head.to_js(indent), atom, indent),
+            Self::Index { head, index } => {
+                format!("{}[{}]", head.to_js(indent), index.to_js(indent))
+            }
+        }
+    }
+}
@@ -1,12 +1,15 @@
 use std::{
+    error::Error,
+    fmt::Display,
     iter::Peekable,
     num::{ParseFloatError, ParseIntError},
     str::Chars,
 };
 
 use crate::{
+    grammar,
     position::{Located, Position},
-    Switch,
+    CancelToken, Switch,
 };
 
 #[derive(Debug, Clone)]
@@ -14,6 +17,15 @@ pub struct Lexer<'a> {
     pub text: Peekable<Chars<'a>>,
     pub ln: usize,
     pub col: usize,
+    pub limits: LexerLimits,
+    pub cancel: Option<CancelToken>,
+}
+/// Upper bounds for lexing untrusted input, lengths are in bytes
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexerLimits {
+    pub max_string_len: usize,
+    pub max_ident_len: usize,
+    pub max_tokens: usize,
 }
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
@@ -21,6 +33,8 @@ pub enum Token {
     Integer(i64),
     Decimal(f64),
     String(String),
+    Bool(bool),
+    Null,
     ParanLeft,
     ParanRight,
     BracketLeft,
@@ -30,6 +44,30 @@ pub enum Token {
     Equal,
     Semicolon,
     Dot,
+    Pipe,
+    Comma,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    EqualEqual,
+    ExclamationEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Exclamation,
+    If,
+    Else,
+    While,
+    Fn,
+    For,
+    In,
+    Break,
+    Continue,
+    Return,
+    Ellipsis,
 }
 #[derive(Debug, Clone, PartialEq)]
 pub enum LexError {
@@ -38,6 +76,59 @@ pub enum LexError {
     ParseFloatError(ParseFloatError),
     ExpectedEscapeCharacter,
     UnclosedString,
+    StringTooLong,
+    IdentTooLong,
+    TooManyTokens,
+    Cancelled,
+}
+/// Literals as written in the source, other tokens by their symbol or keyword
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ident(ident) => write!(f, "{ident}"),
+            Self::Integer(int) => write!(f, "{int}"),
+            Self::Decimal(float) => write!(f, "{float:?}"),
+            Self::String(string) => write!(f, "{string:?}"),
+            Self::Bool(value) => write!(f, "{value}"),
+            token => {
+                let kind = grammar::token_kind(token.kind());
+                write!(f, "{}", kind.map_or(token.kind(), |kind| kind.pattern))
+            }
+        }
+    }
+}
+impl Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BadCharacter(c) => write!(f, "bad character {c:?}"),
+            Self::ParseIntError(err) => write!(f, "invalid integer: {err}"),
+            Self::ParseFloatError(err) => write!(f, "invalid decimal: {err}"),
+            Self::ExpectedEscapeCharacter => write!(f, "expected a character to escape"),
+            Self::UnclosedString => write!(f, "unclosed string"),
+            Self::StringTooLong => write!(f, "string is too long"),
+            Self::IdentTooLong => write!(f, "identifier is too long"),
+            Self::TooManyTokens => write!(f, "too many tokens"),
+            Self::Cancelled => write!(f, "lexing was cancelled"),
+        }
+    }
+}
+impl Error for LexError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::ParseIntError(err) => Some(err),
+            Self::ParseFloatError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+impl Default for LexerLimits {
+    fn default() -> Self {
+        Self {
+            max_string_len: usize::MAX,
+            max_ident_len: usize::MAX,
+            max_tokens: usize::MAX,
+        }
+    }
 }
 impl<'a> Lexer<'a> {
     pub fn new(text: &'a str) -> Self {
@@ -45,15 +136,37 @@ impl<'a> Lexer<'a> {
             text: text.chars().peekable(),
             ln: 0,
             col: 0,
+            limits: LexerLimits::default(),
+            cancel: None,
         }
     }
+    pub fn with_limits(mut self, limits: LexerLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+    pub fn with_cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
     pub fn lex(&mut self) -> Result<Vec<Located<Token>>, Located<LexError>> {
         let mut tokens = vec![];
         while let Some(token) = self.next().switch()? {
+            if self.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+                return Err(Located::new(LexError::Cancelled, token.pos));
+            }
+            if tokens.len() >= self.limits.max_tokens {
+                return Err(Located::new(LexError::TooManyTokens, token.pos));
+            }
             tokens.push(token);
         }
         Ok(tokens)
     }
+    /// Lexes the whole input, guaranteeing not to panic on any input and that `Ok` tokens
+    /// cover everything up to the end of the text: `lex` only stops at the end of the text
+    /// or on a located error, never silently truncating the token stream
+    pub fn lex_checked(&mut self) -> Result<Vec<Located<Token>>, Located<LexError>> {
+        self.lex()
+    }
     pub fn advance(&mut self) -> Option<char> {
         let c = self.text.next();
         if c == Some('\n') {
@@ -86,9 +199,9 @@ impl<'a> Iterator for Lexer<'a> {
                 if c == '\n' {
                     break;
                 }
-                self.advance()?;
+                self.advance();
             }
-            self.advance()?;
+            self.advance();
             self.skip_whitespace()?;
         }
         let mut pos = self.pos();
@@ -100,9 +213,42 @@ impl<'a> Iterator for Lexer<'a> {
             ']' => Some(Ok(Located::new(Token::BracketRight, pos))),
             '{' => Some(Ok(Located::new(Token::BraceLeft, pos))),
             '}' => Some(Ok(Located::new(Token::BraceRight, pos))),
+            '=' | '!' | '<' | '>' if self.text.peek().copied() == Some('=') => {
+                pos.extend(&self.pos());
+                self.advance();
+                Some(Ok(Located::new(
+                    match c {
+                        '=' => Token::EqualEqual,
+                        '!' => Token::ExclamationEqual,
+                        '<' => Token::LessEqual,
+                        _ => Token::GreaterEqual,
+                    },
+                    pos,
+                )))
+            }
             '=' => Some(Ok(Located::new(Token::Equal, pos))),
+            '!' => Some(Ok(Located::new(Token::Exclamation, pos))),
+            '<' => Some(Ok(Located::new(Token::Less, pos))),
+            '>' => Some(Ok(Located::new(Token::Greater, pos))),
+            '+' => Some(Ok(Located::new(Token::Plus, pos))),
+            '-' => Some(Ok(Located::new(Token::Minus, pos))),
+            '*' => Some(Ok(Located::new(Token::Star, pos))),
+            '/' => Some(Ok(Located::new(Token::Slash, pos))),
+            '%' => Some(Ok(Located::new(Token::Percent, pos))),
             ';' => Some(Ok(Located::new(Token::Semicolon, pos))),
+            '.' if self.text.clone().take(2).eq(['.', '.']) => {
+                self.advance();
+                pos.extend(&self.pos());
+                self.advance();
+                Some(Ok(Located::new(Token::Ellipsis, pos)))
+            }
             '.' => Some(Ok(Located::new(Token::Dot, pos))),
+            ',' => Some(Ok(Located::new(Token::Comma, pos))),
+            '|' if self.text.peek().copied() == Some('>') => {
+                pos.extend(&self.pos());
+                self.advance();
+                Some(Ok(Located::new(Token::Pipe, pos)))
+            }
             end_c if end_c == '"' || end_c == '\'' => {
                 let mut string = String::new();
                 while let Some(c) = self.text.peek().copied() {
@@ -111,7 +257,7 @@ impl<'a> Iterator for Lexer<'a> {
                     }
                     string.push(match c {
                         '\\' => {
-                            self.advance()?;
+                            self.advance();
                             let Some(c) = self.advance() else {
                                 return Some(Err(Located::new(
                                     LexError::ExpectedEscapeCharacter,
@@ -145,12 +291,18 @@ impl<'a> Iterator for Lexer<'a> {
                                 c => c,
                             }
                         }
-                        c => c,
+                        c => {
+                            self.advance();
+                            c
+                        }
                     });
-                    self.advance();
+                    if string.len() > self.limits.max_string_len {
+                        pos.extend(&self.pos());
+                        return Some(Err(Located::new(LexError::StringTooLong, pos)));
+                    }
                 }
                 pos.extend(&self.pos());
-                if self.text.next() != Some(end_c) {
+                if self.advance() != Some(end_c) {
                     return Some(Err(Located::new(LexError::UnclosedString, pos)));
                 }
                 Some(Ok(Located::new(Token::String(string), pos)))
@@ -215,8 +367,28 @@ impl<'a> Iterator for Lexer<'a> {
                     ident.push(c);
                     pos.extend(&self.pos());
                     self.advance();
+                    if ident.len() > self.limits.max_ident_len {
+                        return Some(Err(Located::new(LexError::IdentTooLong, pos)));
+                    }
                 }
-                Some(Ok(Located::new(Token::Ident(ident), pos)))
+                Some(Ok(Located::new(
+                    match ident.as_str() {
+                        "true" => Token::Bool(true),
+                        "false" => Token::Bool(false),
+                        "null" => Token::Null,
+                        "if" => Token::If,
+                        "else" => Token::Else,
+                        "while" => Token::While,
+                        "fn" => Token::Fn,
+                        "for" => Token::For,
+                        "in" => Token::In,
+                        "break" => Token::Break,
+                        "continue" => Token::Continue,
+                        "return" => Token::Return,
+                        _ => Token::Ident(ident),
+                    },
+                    pos,
+                )))
             }
             c => Some(Err(Located::new(LexError::BadCharacter(c), pos))),
         }
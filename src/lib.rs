@@ -2,9 +2,29 @@
 mod tests;
 pub mod position;
 pub mod lexer;
+pub mod token_stream;
+pub mod testing;
 pub mod parser;
 pub mod ir;
 pub mod compiler;
+pub mod interpreter;
+pub mod grammar;
+pub mod diagnostics;
+#[cfg(feature = "js")]
+pub mod js;
+
+use std::{
+    fmt::Display,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use compiler::CompileError;
+use ir::{optimize::OptLevel, Closure, LinkError};
+use lexer::{LexError, Lexer};
+use parser::{Parsable, ParseError, Parser, Program};
+use position::{Located, Position};
 
 pub trait Switch {
     type Item;
@@ -22,3 +42,93 @@ impl<T, E> Switch for Option<Result<T, E>> {
         }
     }
 }
+
+/// Shared flag a host can set from another thread to abort lexing, parsing or compiling
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Error of any stage between source text and a runnable closure
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Lex(Located<LexError>),
+    Parse(Located<ParseError>),
+    Compile(Located<CompileError>),
+    Link(Located<LinkError>),
+}
+impl Error {
+    pub fn pos(&self) -> &Position {
+        match self {
+            Self::Lex(err) => &err.pos,
+            Self::Parse(err) => &err.pos,
+            Self::Compile(err) => &err.pos,
+            Self::Link(err) => &err.pos,
+        }
+    }
+}
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Lex(err) => err.fmt(f),
+            Self::Parse(err) => err.fmt(f),
+            Self::Compile(err) => err.fmt(f),
+            Self::Link(err) => err.fmt(f),
+        }
+    }
+}
+/// The position is part of the message, so the source is the wrapped error's own source
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Lex(err) => err.source(),
+            Self::Parse(err) => err.source(),
+            Self::Compile(err) => err.source(),
+            Self::Link(err) => err.source(),
+        }
+    }
+}
+impl From<Located<LexError>> for Error {
+    fn from(err: Located<LexError>) -> Self {
+        Self::Lex(err)
+    }
+}
+impl From<Located<ParseError>> for Error {
+    fn from(err: Located<ParseError>) -> Self {
+        Self::Parse(err)
+    }
+}
+impl From<Located<CompileError>> for Error {
+    fn from(err: Located<CompileError>) -> Self {
+        Self::Compile(err)
+    }
+}
+impl From<Located<LinkError>> for Error {
+    fn from(err: Located<LinkError>) -> Self {
+        Self::Link(err)
+    }
+}
+
+/// Lexes and parses `src` into a program
+pub fn parse(src: &str) -> Result<Located<Program>, Error> {
+    let tokens = Lexer::new(src).lex()?;
+    Ok(Program::parse(&mut Parser::new(tokens))?)
+}
+/// Parses and compiles `src` with constant folding and all optimizations, resolving labels so
+/// the closure is ready to run
+pub fn compile(src: &str) -> Result<Closure, Error> {
+    let mut program = parse(src)?;
+    parser::fold(&mut program.value);
+    let mut closure = compiler::compile(&program, OptLevel::Full)?;
+    closure.resolve_labels()?;
+    Ok(closure)
+}
@@ -1,10 +1,58 @@
 use crate::{
     lexer::Token,
     position::{Located, Position},
+    CancelToken,
 };
-use std::{iter::Peekable, vec::IntoIter};
+use std::{error::Error, fmt::Display, iter::Peekable, ops::Range, vec::IntoIter};
 
-pub type Parser = Peekable<IntoIter<Located<Token>>>;
+/// Tokens being parsed, optionally checking a `CancelToken` before every statement and expression
+pub struct Parser {
+    tokens: Peekable<IntoIter<Located<Token>>>,
+    cancel: Option<CancelToken>,
+    /// Set by `separated` for the next expression, which may be followed by another item after
+    /// nothing but whitespace
+    spaced_item: bool,
+    /// First `-` in a spaced item that reads like a sign, an error unless the item turns out to
+    /// be followed by a comma
+    ambiguous_minus: Option<Position>,
+}
+impl Parser {
+    pub fn new(tokens: Vec<Located<Token>>) -> Self {
+        Self {
+            tokens: tokens.into_iter().peekable(),
+            cancel: None,
+            spaced_item: false,
+            ambiguous_minus: None,
+        }
+    }
+    pub fn with_cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+    pub fn peek(&mut self) -> Option<&Located<Token>> {
+        self.tokens.peek()
+    }
+    /// Fails with `Cancelled` at the next token once the cancel token is set
+    fn check_cancel(&mut self) -> Result<(), Located<ParseError>> {
+        if self.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+            let pos = self.peek().map(|token| token.pos.clone()).unwrap_or_default();
+            return Err(Located::new(ParseError::Cancelled, pos));
+        }
+        Ok(())
+    }
+}
+impl Iterator for Parser {
+    type Item = Located<Token>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.tokens.next()
+    }
+}
+impl From<Vec<Located<Token>>> for Parser {
+    fn from(tokens: Vec<Located<Token>>) -> Self {
+        Self::new(tokens)
+    }
+}
+const MAP_KEYS: &[Token] = &[Token::Ident(String::new()), Token::String(String::new())];
 #[derive(Debug, Clone, PartialEq)]
 pub enum ParseError {
     UnexpectedEOF,
@@ -17,16 +65,72 @@ pub enum ParseError {
         expected: &'static [Token],
         got: Token,
     },
+    Cancelled,
+    TrailingTokens,
+    MissingComma {
+        got: Token,
+    },
+    UnexpectedComma,
+    /// `a -b` between items separated by whitespace, which could also be the items `a` and `-b`
+    AmbiguousMinus,
+    /// Left-hand side of `=` that is not a variable, field or index
+    NotAssignable,
+}
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnexpectedEOF => write!(f, "unexpected end of input"),
+            Self::UnexpectedToken(token) => write!(f, "unexpected {}", describe(token)),
+            Self::ExpectedToken { expected, got } => {
+                write!(f, "expected {}, got {}", describe(expected), describe(got))
+            }
+            Self::ExpectedTokens { expected, got } => {
+                let expected: Vec<String> = expected.iter().map(describe).collect();
+                write!(f, "expected one of {}, got {}", expected.join(", "), describe(got))
+            }
+            Self::Cancelled => write!(f, "parsing was cancelled"),
+            Self::TrailingTokens => write!(f, "unexpected tokens after the end"),
+            Self::MissingComma { got } => write!(f, "expected `,` before {}", describe(got)),
+            Self::UnexpectedComma => write!(f, "unexpected `,`"),
+            Self::AmbiguousMinus => write!(
+                f,
+                "ambiguous `-` between items, separate them with commas or space out the `-`"
+            ),
+            Self::NotAssignable => write!(f, "cannot assign to this expression"),
+        }
+    }
+}
+impl Error for ParseError {}
+/// Token as named in errors, placeholder identifiers and strings stand for any of their kind
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Ident(ident) if ident.is_empty() => "identifier".to_string(),
+        Token::String(string) if string.is_empty() => "string".to_string(),
+        Token::Ident(_) => format!("identifier `{token}`"),
+        Token::Integer(_) | Token::Decimal(_) | Token::String(_) => format!("literal {token}"),
+        token => format!("`{token}`"),
+    }
 }
 pub trait Parsable
 where
     Self: Sized,
 {
     fn parse(parser: &mut Parser) -> Result<Located<Self>, Located<ParseError>>;
+    /// Parses like `parse` but fails with `TrailingTokens` spanning any unconsumed input
+    fn parse_complete(parser: &mut Parser) -> Result<Located<Self>, Located<ParseError>> {
+        let node = Self::parse(parser)?;
+        if let Some(Located { value: _, mut pos }) = parser.next() {
+            for token in parser.by_ref() {
+                pos.extend(&token.pos);
+            }
+            return Err(Located::new(ParseError::TrailingTokens, pos));
+        }
+        Ok(node)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct Program(Vec<Located<Statement>>);
+pub struct Program(pub Vec<Located<Statement>>);
 #[derive(Debug, Clone, PartialEq)]
 pub enum Statement {
     Assign {
@@ -34,17 +138,84 @@ pub enum Statement {
         expr: Located<Expression>,
     },
     Call {
-        head: Located<Path>,
-        args: Vec<Located<Expression>>,
+        head: Located<Expression>,
+        args: Vec<Argument>,
     },
+    If {
+        cond: Located<Expression>,
+        case: Vec<Located<Self>>,
+        else_case: Option<Vec<Located<Self>>>,
+    },
+    While {
+        cond: Located<Expression>,
+        body: Vec<Located<Self>>,
+    },
+    For {
+        var: Located<String>,
+        iter: Located<Expression>,
+        body: Vec<Located<Self>>,
+    },
+    Function {
+        name: Located<String>,
+        params: Vec<Located<String>>,
+        body: Vec<Located<Self>>,
+    },
+    Break,
+    Continue,
+    Return(Option<Located<Expression>>),
+    Block(Vec<Located<Self>>),
 }
+/// A call argument, named if it was passed as `name = expr`
+pub type Argument = (Option<Located<String>>, Located<Expression>);
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expression {
     Atom(Atom),
     Call {
         head: Box<Located<Self>>,
-        args: Vec<Located<Self>>,
+        args: Vec<Argument>,
+    },
+    Binary {
+        op: BinaryOperator,
+        left: Box<Located<Self>>,
+        right: Box<Located<Self>>,
+    },
+    Unary {
+        op: UnaryOperator,
+        expr: Box<Located<Self>>,
+    },
+    Function {
+        params: Vec<Located<String>>,
+        body: Vec<Located<Statement>>,
+    },
+    Field {
+        head: Box<Located<Self>>,
+        field: Box<Located<Atom>>,
+    },
+    Index {
+        head: Box<Located<Self>>,
+        index: Box<Located<Self>>,
     },
+    /// `...expr`, only valid as a list item or positional argument
+    Spread(Box<Located<Self>>),
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOperator {
+    Neg,
+    Not,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOperator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
 }
 #[derive(Debug, Clone, PartialEq)]
 pub enum Atom {
@@ -52,6 +223,8 @@ pub enum Atom {
     Integer(i64),
     Decimal(f64),
     String(String),
+    Bool(bool),
+    Null,
     Expression(Box<Located<Expression>>),
     List(Vec<Located<Expression>>),
     Map(Vec<(Located<String>, Located<Expression>)>),
@@ -60,9 +233,74 @@ pub enum Atom {
 pub enum Path {
     Ident(String),
     Field {
-        head: Box<Located<Self>>,
+        head: Box<Located<Expression>>,
         field: Box<Located<Atom>>,
     },
+    Index {
+        head: Box<Located<Expression>>,
+        index: Box<Located<Expression>>,
+    },
+}
+
+impl UnaryOperator {
+    pub fn from_token(token: &Token) -> Option<Self> {
+        match token {
+            Token::Minus => Some(Self::Neg),
+            Token::Exclamation => Some(Self::Not),
+            _ => None,
+        }
+    }
+}
+impl BinaryOperator {
+    pub fn from_token(token: &Token) -> Option<Self> {
+        match token {
+            Token::Plus => Some(Self::Add),
+            Token::Minus => Some(Self::Sub),
+            Token::Star => Some(Self::Mul),
+            Token::Slash => Some(Self::Div),
+            Token::Percent => Some(Self::Mod),
+            Token::EqualEqual => Some(Self::Eq),
+            Token::ExclamationEqual => Some(Self::Ne),
+            Token::Less => Some(Self::Lt),
+            Token::LessEqual => Some(Self::Le),
+            Token::Greater => Some(Self::Gt),
+            Token::GreaterEqual => Some(Self::Ge),
+            _ => None,
+        }
+    }
+    /// Binding power, higher binds tighter; all binary operators are left associative
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Self::Eq | Self::Ne | Self::Lt | Self::Le | Self::Gt | Self::Ge => 1,
+            Self::Add | Self::Sub => 2,
+            Self::Mul | Self::Div | Self::Mod => 3,
+        }
+    }
+}
+impl Display for UnaryOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Neg => write!(f, "-"),
+            Self::Not => write!(f, "!"),
+        }
+    }
+}
+impl Display for BinaryOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Mod => "%",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+        })
+    }
 }
 
 impl Parsable for Program {
@@ -79,33 +317,147 @@ impl Parsable for Program {
 }
 impl Parsable for Statement {
     fn parse(parser: &mut Parser) -> Result<Located<Self>, Located<ParseError>> {
-        let path = Path::parse(parser)?;
-        let mut pos = path.pos.clone();
-        let Some(Located {
-            value: c_token,
-            pos: c_pos,
-        }) = parser.next()
-        else {
-            return Err(Located::new(ParseError::UnexpectedEOF, Position::default()));
-        };
-        let stat = match c_token {
-            Token::Equal => {
-                let expr = Expression::parse(parser)?;
-                pos.extend(&expr.pos);
-                Located::new(Self::Assign { path, expr }, pos)
-            }
-            Token::ParanLeft => {
-                let mut args = vec![];
-                while let Some(Located {
-                    value: c_token,
+        parser.check_cancel()?;
+        if let Some(Located {
+            value: Token::If,
+            pos,
+        }) = parser.peek()
+        {
+            let mut pos = pos.clone();
+            parser.next();
+            let cond = Expression::parse(parser)?;
+            let (case, c_pos) = Self::block(parser)?;
+            pos.extend(&c_pos);
+            let mut else_case = None;
+            if let Some(Located {
+                value: Token::Else,
+                pos: _,
+            }) = parser.peek()
+            {
+                parser.next();
+                if let Some(Located {
+                    value: Token::If,
                     pos: _,
                 }) = parser.peek()
                 {
-                    if c_token == &Token::ParanRight {
-                        break;
-                    }
-                    args.push(Expression::parse(parser)?);
+                    let stat = Self::parse(parser)?;
+                    pos.extend(&stat.pos);
+                    else_case = Some(vec![stat]);
+                } else {
+                    let (stats, c_pos) = Self::block(parser)?;
+                    pos.extend(&c_pos);
+                    else_case = Some(stats);
                 }
+            }
+            return Ok(Located::new(
+                Self::If {
+                    cond,
+                    case,
+                    else_case,
+                },
+                pos,
+            ));
+        }
+        if let Some(Located {
+            value: Token::While,
+            pos,
+        }) = parser.peek()
+        {
+            let mut pos = pos.clone();
+            parser.next();
+            let cond = Expression::parse(parser)?;
+            let (body, c_pos) = Self::block(parser)?;
+            pos.extend(&c_pos);
+            return Ok(Located::new(Self::While { cond, body }, pos));
+        }
+        if let Some(Located {
+            value: c_token @ (Token::Break | Token::Continue),
+            pos,
+        }) = parser.peek()
+        {
+            let stat = if c_token == &Token::Break {
+                Self::Break
+            } else {
+                Self::Continue
+            };
+            let mut pos = pos.clone();
+            parser.next();
+            pos.extend(&expect(parser, Token::Semicolon)?);
+            return Ok(Located::new(stat, pos));
+        }
+        if let Some(Located {
+            value: Token::BraceLeft,
+            pos: _,
+        }) = parser.peek()
+        {
+            let (stats, pos) = Self::block(parser)?;
+            return Ok(Located::new(Self::Block(stats), pos));
+        }
+        if let Some(Located {
+            value: Token::Return,
+            pos,
+        }) = parser.peek()
+        {
+            let mut pos = pos.clone();
+            parser.next();
+            let expr = match parser.peek() {
+                Some(Located {
+                    value: Token::Semicolon,
+                    pos: _,
+                }) => None,
+                _ => Some(Expression::parse(parser)?),
+            };
+            pos.extend(&expect(parser, Token::Semicolon)?);
+            return Ok(Located::new(Self::Return(expr), pos));
+        }
+        if let Some(Located {
+            value: Token::For,
+            pos,
+        }) = parser.peek()
+        {
+            let mut pos = pos.clone();
+            parser.next();
+            let var = ident(parser)?;
+            expect(parser, Token::In)?;
+            let iter = Expression::parse(parser)?;
+            let (body, c_pos) = Self::block(parser)?;
+            pos.extend(&c_pos);
+            return Ok(Located::new(Self::For { var, iter, body }, pos));
+        }
+        if let Some(Located {
+            value: Token::Fn,
+            pos,
+        }) = parser.peek()
+        {
+            let mut pos = pos.clone();
+            parser.next();
+            let name = ident(parser)?;
+            expect(parser, Token::ParanLeft)?;
+            let (params, _) = separated(parser, Token::ParanRight, ident)?;
+            let (body, c_pos) = Self::block(parser)?;
+            pos.extend(&c_pos);
+            return Ok(Located::new(Self::Function { name, params, body }, pos));
+        }
+        let head = Expression::postfix(parser)?;
+        let mut pos = head.pos.clone();
+        let stat = match (head.value, parser.peek()) {
+            (
+                value,
+                Some(Located {
+                    value: Token::Equal,
+                    pos: _,
+                }),
+            ) => {
+                let path = Path::assignable(Located::new(value, head.pos))?;
+                parser.next();
+                let expr = Expression::parse(parser)?;
+                pos.extend(&expr.pos);
+                Located::new(Self::Assign { path, expr }, pos)
+            }
+            (Expression::Call { head, args }, _) => {
+                Located::new(Self::Call { head: *head, args }, pos)
+            }
+            (_, _) => {
                 let Some(Located {
                     value: c_token,
                     pos: c_pos,
@@ -113,26 +465,13 @@ impl Parsable for Statement {
                 else {
                     return Err(Located::new(ParseError::UnexpectedEOF, Position::default()));
                 };
-                if c_token != Token::ParanRight {
-                    return Err(Located::new(
-                        ParseError::ExpectedToken {
-                            expected: Token::ParanRight,
-                            got: c_token,
-                        },
-                        c_pos,
-                    ));
-                }
-                pos.extend(&c_pos);
-                Located::new(Self::Call { head: path, args }, pos)
-            }
-            c_token => {
                 return Err(Located::new(
                     ParseError::ExpectedTokens {
                         expected: &[Token::Equal, Token::ParanLeft],
                         got: c_token,
                     },
                     c_pos,
-                ))
+                ));
             }
         };
         let Some(Located {
@@ -154,45 +493,147 @@ impl Parsable for Statement {
         Ok(stat)
     }
 }
+impl Statement {
+    /// Parses `{ statements }`, returning the statements with the position of the whole block
+    fn block(parser: &mut Parser) -> Result<(Vec<Located<Self>>, Position), Located<ParseError>> {
+        let mut pos = expect(parser, Token::BraceLeft)?;
+        let mut stats = vec![];
+        while let Some(Located {
+            value: c_token,
+            pos: _,
+        }) = parser.peek()
+        {
+            if c_token == &Token::BraceRight {
+                break;
+            }
+            stats.push(Self::parse(parser)?);
+        }
+        pos.extend(&expect(parser, Token::BraceRight)?);
+        Ok((stats, pos))
+    }
+}
 impl Parsable for Expression {
     fn parse(parser: &mut Parser) -> Result<Located<Self>, Located<ParseError>> {
-        let mut head = Atom::parse(parser)?.map(Self::Atom);
+        parser.check_cancel()?;
+        let spaced_item = std::mem::take(&mut parser.spaced_item);
+        let mut head = Self::binary(parser, 0, spaced_item)?;
+        while let Some(Located {
+            value: Token::Pipe,
+            pos: _,
+        }) = parser.peek()
+        {
+            parser.next();
+            let mut pos = head.pos.clone();
+            let func = Atom::parse(parser)?.map(Self::Atom);
+            pos.extend(&func.pos);
+            let mut args = vec![(None, head)];
+            if let Some(Located {
+                value: Token::ParanLeft,
+                pos: _,
+            }) = parser.peek()
+            {
+                parser.next();
+                let (rest, c_pos) = Self::args(parser)?;
+                args.extend(rest);
+                pos.extend(&c_pos);
+            }
+            head = Located::new(
+                Self::Call {
+                    head: Box::new(func),
+                    args,
+                },
+                pos,
+            );
+        }
+        Ok(head)
+    }
+}
+impl Expression {
+    /// Precedence climbing over binary operators binding tighter than `min_prec`. In a
+    /// `spaced_item`, a `-` spaced like a sign as in `[1 -2]` is recorded as ambiguous for
+    /// `separated` to reject
+    fn binary(
+        parser: &mut Parser,
+        min_prec: u8,
+        spaced_item: bool,
+    ) -> Result<Located<Self>, Located<ParseError>> {
+        let mut left = Self::unary(parser)?;
+        while let Some((op, op_pos)) = parser.peek().and_then(|token| {
+            BinaryOperator::from_token(&token.value).map(|op| (op, token.pos.clone()))
+        }) {
+            let prec = op.precedence();
+            if prec <= min_prec {
+                break;
+            }
+            parser.next();
+            if spaced_item && op == BinaryOperator::Sub {
+                let spaced_before =
+                    left.pos.ln.end != op_pos.ln.start || left.pos.col.end != op_pos.col.start;
+                let attached_after = parser.peek().is_some_and(|token| {
+                    token.pos.ln.start == op_pos.ln.end && token.pos.col.start == op_pos.col.end
+                });
+                if spaced_before && attached_after && parser.ambiguous_minus.is_none() {
+                    parser.ambiguous_minus = Some(op_pos);
+                }
+            }
+            let right = Self::binary(parser, prec, spaced_item)?;
+            let mut pos = left.pos.clone();
+            pos.extend(&right.pos);
+            left = Located::new(
+                Self::Binary {
+                    op,
+                    left: Box::new(left),
+                    right: Box::new(right),
+                },
+                pos,
+            );
+        }
+        Ok(left)
+    }
+    /// Prefix operators, binding tighter than any binary operator but looser than calls
+    fn unary(parser: &mut Parser) -> Result<Located<Self>, Located<ParseError>> {
+        let Some((op, mut pos)) = parser.peek().and_then(|token| {
+            UnaryOperator::from_token(&token.value).map(|op| (op, token.pos.clone()))
+        }) else {
+            return Self::postfix(parser);
+        };
+        parser.next();
+        let expr = Self::unary(parser)?;
+        pos.extend(&expr.pos);
+        Ok(Located::new(
+            Self::Unary {
+                op,
+                expr: Box::new(expr),
+            },
+            pos,
+        ))
+    }
+    fn postfix(parser: &mut Parser) -> Result<Located<Self>, Located<ParseError>> {
+        let mut head = if let Some(Located {
+            value: Token::Fn,
+            pos,
+        }) = parser.peek()
+        {
+            let mut pos = pos.clone();
+            parser.next();
+            expect(parser, Token::ParanLeft)?;
+            let (params, _) = separated(parser, Token::ParanRight, ident)?;
+            let (body, c_pos) = Statement::block(parser)?;
+            pos.extend(&c_pos);
+            Located::new(Self::Function { params, body }, pos)
+        } else {
+            Atom::parse(parser)?.map(Self::Atom)
+        };
         while let Some(Located {
             value: c_token,
             pos: _,
         }) = parser.peek()
         {
+            let mut pos = head.pos.clone();
             head = match c_token {
                 Token::ParanLeft => {
                     parser.next();
-                    let mut pos = head.pos.clone();
-                    let mut args = vec![];
-                    while let Some(Located {
-                        value: c_token,
-                        pos: _,
-                    }) = parser.peek()
-                    {
-                        if c_token == &Token::ParanRight {
-                            break;
-                        }
-                        args.push(Expression::parse(parser)?);
-                    }
-                    let Some(Located {
-                        value: c_token,
-                        pos: c_pos,
-                    }) = parser.next()
-                    else {
-                        return Err(Located::new(ParseError::UnexpectedEOF, Position::default()));
-                    };
-                    if c_token != Token::ParanRight {
-                        return Err(Located::new(
-                            ParseError::ExpectedToken {
-                                expected: Token::ParanRight,
-                                got: c_token,
-                            },
-                            c_pos,
-                        ));
-                    }
+                    let (args, c_pos) = Self::args(parser)?;
                     pos.extend(&c_pos);
                     Located::new(
                         Self::Call {
@@ -202,11 +643,213 @@ impl Parsable for Expression {
                         pos,
                     )
                 }
+                Token::Dot => {
+                    let field = field(parser)?;
+                    pos.extend(&field.pos);
+                    Located::new(
+                        Self::Field {
+                            head: Box::new(head),
+                            field: Box::new(field),
+                        },
+                        pos,
+                    )
+                }
+                Token::BracketLeft => {
+                    let (index, c_pos) = Self::index(parser)?;
+                    pos.extend(&c_pos);
+                    Located::new(
+                        Self::Index {
+                            head: Box::new(head),
+                            index: Box::new(index),
+                        },
+                        pos,
+                    )
+                }
                 _ => break,
             };
         }
         Ok(head)
     }
+    /// Parses `[expr]`, returning the index with the position of the closing `]`
+    fn index(parser: &mut Parser) -> Result<(Located<Self>, Position), Located<ParseError>> {
+        expect(parser, Token::BracketLeft)?;
+        let index = Self::parse(parser)?;
+        let pos = expect(parser, Token::BracketRight)?;
+        Ok((index, pos))
+    }
+    /// Parses a list item or positional argument, which may be spread with `...`
+    fn item(parser: &mut Parser) -> Result<Located<Self>, Located<ParseError>> {
+        let Some(Located {
+            value: Token::Ellipsis,
+            pos,
+        }) = parser.peek()
+        else {
+            return Self::parse(parser);
+        };
+        let mut pos = pos.clone();
+        parser.next();
+        let expr = Self::parse(parser)?;
+        pos.extend(&expr.pos);
+        Ok(Located::new(Self::Spread(Box::new(expr)), pos))
+    }
+    /// Parses call arguments after the opening `(`, returning them with the position of the closing `)`
+    fn args(parser: &mut Parser) -> Result<(Vec<Argument>, Position), Located<ParseError>> {
+        separated(parser, Token::ParanRight, |parser| {
+            let spaced_item = parser.spaced_item;
+            let expr = Self::item(parser)?;
+            match (expr.value, parser.peek()) {
+                (
+                    Self::Atom(Atom::Path(Path::Ident(name))),
+                    Some(Located {
+                        value: Token::Equal,
+                        pos: _,
+                    }),
+                ) => {
+                    parser.next();
+                    parser.spaced_item = spaced_item;
+                    Ok((Some(Located::new(name, expr.pos)), Self::parse(parser)?))
+                }
+                (value, _) => Ok((None, Located::new(value, expr.pos))),
+            }
+        })
+    }
+}
+fn ident(parser: &mut Parser) -> Result<Located<String>, Located<ParseError>> {
+    let Some(Located {
+        value: c_token,
+        pos: c_pos,
+    }) = parser.next()
+    else {
+        return Err(Located::new(ParseError::UnexpectedEOF, Position::default()));
+    };
+    if let Token::Ident(ident) = c_token {
+        Ok(Located::new(ident, c_pos))
+    } else {
+        Err(Located::new(
+            ParseError::ExpectedToken {
+                expected: Token::Ident(String::new()),
+                got: c_token,
+            },
+            c_pos,
+        ))
+    }
+}
+/// Parses `.field`, where a plain identifier is taken as the field name rather than a variable
+fn field(parser: &mut Parser) -> Result<Located<Atom>, Located<ParseError>> {
+    expect(parser, Token::Dot)?;
+    if matches!(parser.peek(), Some(Located { value: Token::Ident(_), pos: _ })) {
+        Ok(Path::ident(parser)?.map(Atom::Path))
+    } else {
+        Atom::parse(parser)
+    }
+}
+/// Consumes the next token if it is `expected`, returning its position
+fn expect(parser: &mut Parser, expected: Token) -> Result<Position, Located<ParseError>> {
+    let Some(Located {
+        value: c_token,
+        pos: c_pos,
+    }) = parser.next()
+    else {
+        return Err(Located::new(ParseError::UnexpectedEOF, Position::default()));
+    };
+    if c_token != expected {
+        return Err(Located::new(
+            ParseError::ExpectedToken {
+                expected,
+                got: c_token,
+            },
+            c_pos,
+        ));
+    }
+    Ok(c_pos)
+}
+/// Parses items up to and including the closing `end` token. Items are either all separated
+/// by commas, allowing a trailing comma, or all by whitespace, decided by the first separator.
+/// Until a comma was seen, expression items are parsed as `spaced_item`s. An ambiguous `-` in
+/// the first item is only an error once the next separator shows the items are not
+/// comma-separated.
+fn separated<T>(
+    parser: &mut Parser,
+    end: Token,
+    mut item: impl FnMut(&mut Parser) -> Result<T, Located<ParseError>>,
+) -> Result<(Vec<T>, Position), Located<ParseError>> {
+    let outer_minus = parser.ambiguous_minus.take();
+    let mut items = vec![];
+    let mut commas = None;
+    // ambiguous `-` of the first item, pending until the next separator
+    let mut pending_minus = None;
+    while let Some(Located {
+        value: c_token,
+        pos: c_pos,
+    }) = parser.peek()
+    {
+        if c_token == &end {
+            break;
+        }
+        match pending_minus.take() {
+            Some(pos) if c_token != &Token::Comma => {
+                return Err(Located::new(ParseError::AmbiguousMinus, pos))
+            }
+            _ => {}
+        }
+        if c_token == &Token::Comma {
+            if items.is_empty() || commas == Some(false) {
+                return Err(Located::new(ParseError::UnexpectedComma, c_pos.clone()));
+            }
+            commas = Some(true);
+            parser.next();
+            match parser.peek() {
+                Some(Located {
+                    value: Token::Comma,
+                    pos: c_pos,
+                }) => return Err(Located::new(ParseError::UnexpectedComma, c_pos.clone())),
+                Some(Located {
+                    value: c_token,
+                    pos: _,
+                }) if c_token == &end => break,
+                _ => {}
+            }
+        } else if !items.is_empty() {
+            if commas == Some(true) {
+                return Err(Located::new(
+                    ParseError::MissingComma { got: c_token.clone() },
+                    c_pos.clone(),
+                ));
+            }
+            commas = Some(false);
+        }
+        parser.spaced_item = commas != Some(true);
+        let result = item(parser);
+        parser.spaced_item = false;
+        items.push(result?);
+        if let Some(pos) = parser.ambiguous_minus.take() {
+            if commas.is_some() {
+                return Err(Located::new(ParseError::AmbiguousMinus, pos));
+            }
+            pending_minus = Some(pos);
+        }
+    }
+    if let Some(pos) = pending_minus {
+        return Err(Located::new(ParseError::AmbiguousMinus, pos));
+    }
+    let Some(Located {
+        value: c_token,
+        pos: c_pos,
+    }) = parser.next()
+    else {
+        return Err(Located::new(ParseError::UnexpectedEOF, Position::default()));
+    };
+    if c_token != end {
+        return Err(Located::new(
+            ParseError::ExpectedToken {
+                expected: end,
+                got: c_token,
+            },
+            c_pos,
+        ));
+    }
+    parser.ambiguous_minus = outer_minus;
+    Ok((items, c_pos))
 }
 impl Parsable for Atom {
     fn parse(parser: &mut Parser) -> Result<Located<Self>, Located<ParseError>> {
@@ -230,6 +873,8 @@ impl Parsable for Atom {
             Token::Integer(value) => Ok(Located::new(Self::Integer(value), pos)),
             Token::Decimal(value) => Ok(Located::new(Self::Decimal(value), pos)),
             Token::String(value) => Ok(Located::new(Self::String(value), pos)),
+            Token::Bool(value) => Ok(Located::new(Self::Bool(value), pos)),
+            Token::Null => Ok(Located::new(Self::Null, pos)),
             Token::ParanLeft => {
                 let expr = Expression::parse(parser)?;
                 let Some(Located {
@@ -252,16 +897,83 @@ impl Parsable for Atom {
                 Ok(Located::new(Self::Expression(Box::new(expr)), pos))
             }
             Token::BracketLeft => {
-                let mut exprs = vec![];
+                let (exprs, c_pos) = separated(parser, Token::BracketRight, Expression::item)?;
+                pos.extend(&c_pos);
+                Ok(Located::new(Self::List(exprs), pos))
+            }
+            Token::BraceLeft => {
+                let mut pairs = vec![];
                 while let Some(Located {
                     value: c_token,
                     pos: _,
                 }) = parser.peek()
                 {
-                    if c_token == &Token::BracketRight {
+                    if c_token == &Token::BraceRight {
                         break;
                     }
-                    exprs.push(Expression::parse(parser)?);
+                    let Some(Located {
+                        value: c_token,
+                        pos: c_pos,
+                    }) = parser.next()
+                    else {
+                        return Err(Located::new(ParseError::UnexpectedEOF, Position::default()));
+                    };
+                    let key = match c_token {
+                        Token::Ident(key) | Token::String(key) => Located::new(key, c_pos),
+                        c_token => {
+                            return Err(Located::new(
+                                ParseError::ExpectedTokens {
+                                    expected: MAP_KEYS,
+                                    got: c_token,
+                                },
+                                c_pos,
+                            ))
+                        }
+                    };
+                    let Some(Located {
+                        value: c_token,
+                        pos: c_pos,
+                    }) = parser.next()
+                    else {
+                        return Err(Located::new(ParseError::UnexpectedEOF, Position::default()));
+                    };
+                    if c_token != Token::Equal {
+                        return Err(Located::new(
+                            ParseError::ExpectedToken {
+                                expected: Token::Equal,
+                                got: c_token,
+                            },
+                            c_pos,
+                        ));
+                    }
+                    pairs.push((key, Expression::parse(parser)?));
+                    match parser.peek() {
+                        Some(Located {
+                            value: Token::Semicolon,
+                            pos: _,
+                        }) => {
+                            parser.next();
+                        }
+                        Some(Located {
+                            value: Token::BraceRight,
+                            pos: _,
+                        }) => {}
+                        Some(Located {
+                            value: c_token,
+                            pos: c_pos,
+                        }) => {
+                            return Err(Located::new(
+                                ParseError::ExpectedTokens {
+                                    expected: &[Token::Semicolon, Token::BraceRight],
+                                    got: c_token.clone(),
+                                },
+                                c_pos.clone(),
+                            ))
+                        }
+                        None => {
+                            return Err(Located::new(ParseError::UnexpectedEOF, Position::default()))
+                        }
+                    }
                 }
                 let Some(Located {
                     value: c_token,
@@ -270,17 +982,17 @@ impl Parsable for Atom {
                 else {
                     return Err(Located::new(ParseError::UnexpectedEOF, Position::default()));
                 };
-                if c_token != Token::BracketRight {
+                if c_token != Token::BraceRight {
                     return Err(Located::new(
                         ParseError::ExpectedToken {
-                            expected: Token::BracketRight,
+                            expected: Token::BraceRight,
                             got: c_token,
                         },
                         c_pos,
                     ));
                 }
                 pos.extend(&c_pos);
-                Ok(Located::new(Self::List(exprs), pos))
+                Ok(Located::new(Self::Map(pairs), pos))
             }
             token => Err(Located::new(ParseError::UnexpectedToken(token), pos)),
         }
@@ -296,22 +1008,29 @@ impl Parsable for Path {
         {
             head = match c_token {
                 Token::Dot => {
-                    parser.next();
                     let mut pos = head.pos.clone();
-                    let field = if matches!(parser.peek(), Some(Located { value: Token::Ident(_), pos: _ })) {
-                        Self::ident(parser)?.map(Atom::Path)
-                    } else {
-                        Atom::parse(parser)?
-                    };
+                    let field = field(parser)?;
                     pos.extend(&field.pos);
                     Located::new(
                         Self::Field {
-                            head: Box::new(head),
+                            head: Box::new(head.map(|path| Expression::Atom(Atom::Path(path)))),
                             field: Box::new(field),
                         },
                         pos,
                     )
                 }
+                Token::BracketLeft => {
+                    let mut pos = head.pos.clone();
+                    let (index, c_pos) = Expression::index(parser)?;
+                    pos.extend(&c_pos);
+                    Located::new(
+                        Self::Index {
+                            head: Box::new(head.map(|path| Expression::Atom(Atom::Path(path)))),
+                            index: Box::new(index),
+                        },
+                        pos,
+                    )
+                }
                 _ => break,
             };
         }
@@ -320,23 +1039,410 @@ impl Parsable for Path {
 }
 impl Path {
     fn ident(parser: &mut Parser) -> Result<Located<Self>, Located<ParseError>> {
-        let Some(Located {
-            value: c_token,
-            pos: c_pos,
-        }) = parser.next()
-        else {
-            return Err(Located::new(ParseError::UnexpectedEOF, Position::default()));
+        Ok(ident(parser)?.map(Self::Ident))
+    }
+    /// `expr` as the target of an assignment, if it is a variable, field or index
+    fn assignable(expr: Located<Expression>) -> Result<Located<Self>, Located<ParseError>> {
+        let path = match expr.value {
+            Expression::Atom(Atom::Path(path)) => path,
+            Expression::Field { head, field } => Self::Field { head, field },
+            Expression::Index { head, index } => Self::Index { head, index },
+            _ => return Err(Located::new(ParseError::NotAssignable, expr.pos)),
         };
-        if let Token::Ident(ident) = c_token {
-            Ok(Located::new(Self::Ident(ident), c_pos))
-        } else {
-            Err(Located::new(
-                ParseError::ExpectedToken {
-                    expected: Token::BracketRight,
-                    got: c_token,
-                },
-                c_pos,
-            ))
+        Ok(Located::new(path, expr.pos))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Metrics {
+    pub statements: usize,
+    pub max_depth: usize,
+    pub calls: usize,
+    pub integers: usize,
+    pub decimals: usize,
+    pub strings: usize,
+    pub bools: usize,
+    pub nulls: usize,
+    /// Byte offsets into the source text from the first to the last statement
+    pub span: Range<usize>,
+}
+impl Program {
+    /// Measures the program, `text` being the source it was parsed from
+    pub fn metrics(&self, text: &str) -> Metrics {
+        let mut metrics = Metrics::default();
+        for stat in self.0.iter() {
+            metrics.statements += 1;
+            stat.value.measure(&mut metrics);
+        }
+        if let (Some(first), Some(last)) = (self.0.first(), self.0.last()) {
+            metrics.span = first.pos.byte_range(text).start..last.pos.byte_range(text).end;
+        }
+        metrics
+    }
+}
+impl Statement {
+    fn measure(&self, metrics: &mut Metrics) {
+        match self {
+            Self::Assign { path, expr } => {
+                path.value.measure(metrics, 1);
+                expr.value.measure(metrics, 1);
+            }
+            Self::Call { head, args } => {
+                metrics.calls += 1;
+                head.value.measure(metrics, 1);
+                for (_, arg) in args {
+                    arg.value.measure(metrics, 1);
+                }
+            }
+            Self::If {
+                cond,
+                case,
+                else_case,
+            } => {
+                cond.value.measure(metrics, 1);
+                for stat in case.iter().chain(else_case.iter().flatten()) {
+                    metrics.statements += 1;
+                    stat.value.measure(metrics);
+                }
+            }
+            Self::While { cond, body } => {
+                cond.value.measure(metrics, 1);
+                for stat in body {
+                    metrics.statements += 1;
+                    stat.value.measure(metrics);
+                }
+            }
+            Self::For { var: _, iter, body } => {
+                iter.value.measure(metrics, 1);
+                for stat in body {
+                    metrics.statements += 1;
+                    stat.value.measure(metrics);
+                }
+            }
+            Self::Function {
+                name: _,
+                params: _,
+                body,
+            } => {
+                for stat in body {
+                    metrics.statements += 1;
+                    stat.value.measure(metrics);
+                }
+            }
+            Self::Break | Self::Continue => {}
+            Self::Block(stats) => {
+                for stat in stats {
+                    metrics.statements += 1;
+                    stat.value.measure(metrics);
+                }
+            }
+            Self::Return(expr) => {
+                if let Some(expr) = expr {
+                    expr.value.measure(metrics, 1);
+                }
+            }
+        }
+    }
+}
+impl Expression {
+    fn measure(&self, metrics: &mut Metrics, depth: usize) {
+        metrics.max_depth = metrics.max_depth.max(depth);
+        match self {
+            Self::Atom(atom) => atom.measure(metrics, depth),
+            Self::Call { head, args } => {
+                metrics.calls += 1;
+                head.value.measure(metrics, depth + 1);
+                for (_, arg) in args {
+                    arg.value.measure(metrics, depth + 1);
+                }
+            }
+            Self::Binary { op: _, left, right } => {
+                left.value.measure(metrics, depth + 1);
+                right.value.measure(metrics, depth + 1);
+            }
+            Self::Unary { op: _, expr } => expr.value.measure(metrics, depth + 1),
+            Self::Function { params: _, body } => {
+                for stat in body {
+                    metrics.statements += 1;
+                    stat.value.measure(metrics);
+                }
+            }
+            Self::Field { head, field } => {
+                head.value.measure(metrics, depth + 1);
+                field.value.measure(metrics, depth + 1);
+            }
+            Self::Index { head, index } => {
+                head.value.measure(metrics, depth + 1);
+                index.value.measure(metrics, depth + 1);
+            }
+            Self::Spread(expr) => expr.value.measure(metrics, depth + 1),
+        }
+    }
+}
+impl Atom {
+    fn measure(&self, metrics: &mut Metrics, depth: usize) {
+        metrics.max_depth = metrics.max_depth.max(depth);
+        match self {
+            Self::Path(path) => path.measure(metrics, depth),
+            Self::Integer(_) => metrics.integers += 1,
+            Self::Decimal(_) => metrics.decimals += 1,
+            Self::String(_) => metrics.strings += 1,
+            Self::Bool(_) => metrics.bools += 1,
+            Self::Null => metrics.nulls += 1,
+            Self::Expression(expr) => expr.value.measure(metrics, depth + 1),
+            Self::List(exprs) => {
+                for expr in exprs {
+                    expr.value.measure(metrics, depth + 1);
+                }
+            }
+            Self::Map(pairs) => {
+                for (_, expr) in pairs {
+                    expr.value.measure(metrics, depth + 1);
+                }
+            }
+        }
+    }
+}
+impl Path {
+    fn measure(&self, metrics: &mut Metrics, depth: usize) {
+        metrics.max_depth = metrics.max_depth.max(depth);
+        match self {
+            Self::Ident(_) => {}
+            Self::Field { head, field } => {
+                head.value.measure(metrics, depth + 1);
+                field.value.measure(metrics, depth + 1);
+            }
+            Self::Index { head, index } => {
+                head.value.measure(metrics, depth + 1);
+                index.value.measure(metrics, depth + 1);
+            }
+        }
+    }
+}
+
+/// Folds operators on constants, like `1 + 2 * 3` or `"a" + "b"`, into literals. Operations
+/// that could fail or differ at runtime, like division by zero or overflow, are left as they are.
+pub fn fold(program: &mut Program) {
+    for stat in program.0.iter_mut() {
+        stat.value.fold();
+    }
+}
+impl Statement {
+    fn fold(&mut self) {
+        let fold_block = |stats: &mut Vec<Located<Self>>| {
+            for stat in stats.iter_mut() {
+                stat.value.fold();
+            }
+        };
+        match self {
+            Self::Assign { path, expr } => {
+                path.value.fold();
+                expr.value.fold();
+            }
+            Self::Call { head, args } => {
+                head.value.fold();
+                for (_, arg) in args {
+                    arg.value.fold();
+                }
+            }
+            Self::If {
+                cond,
+                case,
+                else_case,
+            } => {
+                cond.value.fold();
+                fold_block(case);
+                if let Some(else_case) = else_case {
+                    fold_block(else_case);
+                }
+            }
+            Self::While { cond, body } => {
+                cond.value.fold();
+                fold_block(body);
+            }
+            Self::For { var: _, iter, body } => {
+                iter.value.fold();
+                fold_block(body);
+            }
+            Self::Function {
+                name: _,
+                params: _,
+                body,
+            } => fold_block(body),
+            Self::Break | Self::Continue | Self::Return(None) => {}
+            Self::Return(Some(expr)) => expr.value.fold(),
+            Self::Block(stats) => fold_block(stats),
+        }
+    }
+}
+impl Expression {
+    fn fold(&mut self) {
+        let folded = match self {
+            Self::Atom(atom) => {
+                atom.fold();
+                match atom {
+                    Atom::Expression(expr) => expr.value.constant().cloned(),
+                    _ => None,
+                }
+            }
+            Self::Call { head, args } => {
+                head.value.fold();
+                for (_, arg) in args {
+                    arg.value.fold();
+                }
+                None
+            }
+            Self::Binary { op, left, right } => {
+                left.value.fold();
+                right.value.fold();
+                left.value
+                    .constant()
+                    .zip(right.value.constant())
+                    .and_then(|(left, right)| op.fold(left, right))
+            }
+            Self::Unary { op, expr } => {
+                expr.value.fold();
+                expr.value.constant().and_then(|atom| op.fold(atom))
+            }
+            Self::Function { params: _, body } => {
+                for stat in body {
+                    stat.value.fold();
+                }
+                None
+            }
+            Self::Field { head, field } => {
+                head.value.fold();
+                field.value.fold();
+                None
+            }
+            Self::Index { head, index } => {
+                head.value.fold();
+                index.value.fold();
+                None
+            }
+            Self::Spread(expr) => {
+                expr.value.fold();
+                None
+            }
+        };
+        if let Some(atom) = folded {
+            *self = Self::Atom(atom);
+        }
+    }
+    /// The literal this expression consists of, seeing through parentheses
+    fn constant(&self) -> Option<&Atom> {
+        match self {
+            Self::Atom(Atom::Expression(expr)) => expr.value.constant(),
+            Self::Atom(
+                atom @ (Atom::Integer(_)
+                | Atom::Decimal(_)
+                | Atom::String(_)
+                | Atom::Bool(_)
+                | Atom::Null),
+            ) => Some(atom),
+            _ => None,
+        }
+    }
+}
+impl Atom {
+    fn fold(&mut self) {
+        match self {
+            Self::Path(path) => path.fold(),
+            Self::Expression(expr) => expr.value.fold(),
+            Self::List(exprs) => {
+                for expr in exprs {
+                    expr.value.fold();
+                }
+            }
+            Self::Map(pairs) => {
+                for (_, expr) in pairs {
+                    expr.value.fold();
+                }
+            }
+            Self::Integer(_) | Self::Decimal(_) | Self::String(_) | Self::Bool(_) | Self::Null => {}
+        }
+    }
+}
+impl Path {
+    fn fold(&mut self) {
+        match self {
+            Self::Ident(_) => {}
+            Self::Field { head, field } => {
+                head.value.fold();
+                field.value.fold();
+            }
+            Self::Index { head, index } => {
+                head.value.fold();
+                index.value.fold();
+            }
+        }
+    }
+}
+impl BinaryOperator {
+    /// Result of the operator on two literals, if it is known at compile time
+    pub fn fold(&self, left: &Atom, right: &Atom) -> Option<Atom> {
+        match (left, right) {
+            (Atom::Integer(left), Atom::Integer(right)) => {
+                let (left, right) = (*left, *right);
+                match self {
+                    Self::Add => left.checked_add(right).map(Atom::Integer),
+                    Self::Sub => left.checked_sub(right).map(Atom::Integer),
+                    Self::Mul => left.checked_mul(right).map(Atom::Integer),
+                    Self::Div => left.checked_div(right).map(Atom::Integer),
+                    Self::Mod => left.checked_rem(right).map(Atom::Integer),
+                    _ => self.compare(left.cmp(&right)),
+                }
+            }
+            (Atom::Integer(_) | Atom::Decimal(_), Atom::Integer(_) | Atom::Decimal(_)) => {
+                let number = |atom: &Atom| match atom {
+                    Atom::Integer(int) => *int as f64,
+                    Atom::Decimal(float) => *float,
+                    _ => unreachable!("operands are numbers"),
+                };
+                let (left, right) = (number(left), number(right));
+                let value = match self {
+                    Self::Add => left + right,
+                    Self::Sub => left - right,
+                    Self::Mul => left * right,
+                    Self::Div => left / right,
+                    Self::Mod => left % right,
+                    _ => return self.compare(left.partial_cmp(&right)?),
+                };
+                value.is_finite().then_some(Atom::Decimal(value))
+            }
+            (Atom::String(left), Atom::String(right)) => match self {
+                Self::Add => Some(Atom::String(format!("{left}{right}"))),
+                Self::Eq | Self::Ne => self.compare(left.cmp(right)),
+                _ => None,
+            },
+            (Atom::Bool(_), Atom::Bool(_)) | (Atom::Null, Atom::Null) => match self {
+                Self::Eq => Some(Atom::Bool(left == right)),
+                Self::Ne => Some(Atom::Bool(left != right)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+    fn compare(&self, ordering: std::cmp::Ordering) -> Option<Atom> {
+        Some(Atom::Bool(match self {
+            Self::Eq => ordering.is_eq(),
+            Self::Ne => ordering.is_ne(),
+            Self::Lt => ordering.is_lt(),
+            Self::Le => ordering.is_le(),
+            Self::Gt => ordering.is_gt(),
+            Self::Ge => ordering.is_ge(),
+            _ => return None,
+        }))
+    }
+}
+impl UnaryOperator {
+    /// Result of the operator on a literal, if it is known at compile time
+    pub fn fold(&self, atom: &Atom) -> Option<Atom> {
+        match (self, atom) {
+            (Self::Neg, Atom::Integer(int)) => int.checked_neg().map(Atom::Integer),
+            (Self::Neg, Atom::Decimal(float)) => Some(Atom::Decimal(-float)),
+            (Self::Not, Atom::Bool(value)) => Some(Atom::Bool(!value)),
+            _ => None,
         }
     }
 }
@@ -1,4 +1,4 @@
-use std::{fmt::{Debug, Display}, ops::Range};
+use std::{cell::Cell, error::Error, fmt::{Debug, Display}, ops::Range};
 
 #[derive(Debug, Clone, PartialEq, Default)]
 pub struct Position {
@@ -9,6 +9,22 @@ pub struct Located<T> {
     pub value: T,
     pub pos: Position
 }
+pub struct DebugWithPos<'a, T>(&'a Located<T>);
+/// Zero-based line and UTF-16 code unit offset, as used by the Language Server Protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LspPosition {
+    pub line: u32,
+    pub character: u32,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LspRange {
+    pub start: LspPosition,
+    pub end: LspPosition,
+}
+
+thread_local! {
+    static DEBUG_POS: Cell<bool> = const { Cell::new(false) };
+}
 
 impl Position {
     pub fn new(ln: Range<usize>, col: Range<usize>) -> Self {
@@ -16,6 +32,60 @@ impl Position {
     }
     pub fn extend(&mut self, other: &Self) {
         self.ln.end = other.ln.end;
+        self.col.end = other.col.end;
+    }
+    /// Converts to an LSP range, translating char columns of `text` into UTF-16 offsets
+    pub fn to_lsp_range(&self, text: &str) -> LspRange {
+        LspRange {
+            start: LspPosition {
+                line: self.ln.start as u32,
+                character: char_to_utf16(line_of(text, self.ln.start), self.col.start) as u32,
+            },
+            end: LspPosition {
+                line: self.ln.end as u32,
+                character: char_to_utf16(line_of(text, self.ln.end), self.col.end) as u32,
+            },
+        }
+    }
+    /// Converts to a range of byte offsets into `text`, translating char columns
+    pub fn byte_range(&self, text: &str) -> Range<usize> {
+        byte_offset(text, self.ln.start, self.col.start)..byte_offset(text, self.ln.end, self.col.end)
+    }
+    /// Converts from an LSP range, translating UTF-16 offsets into char columns of `text`
+    pub fn from_lsp_range(range: &LspRange, text: &str) -> Self {
+        let (start, end) = (range.start, range.end);
+        Self::new(
+            start.line as usize..end.line as usize,
+            utf16_to_char(line_of(text, start.line as usize), start.character as usize)
+                ..utf16_to_char(line_of(text, end.line as usize), end.character as usize),
+        )
+    }
+}
+fn line_of(text: &str, ln: usize) -> &str {
+    text.split('\n').nth(ln).unwrap_or_default()
+}
+fn byte_offset(text: &str, ln: usize, col: usize) -> usize {
+    let start: usize = text.split('\n').take(ln).map(|line| line.len() + 1).sum();
+    let line = line_of(text, ln);
+    start + line.char_indices().nth(col).map_or(line.len(), |(idx, _)| idx)
+}
+fn char_to_utf16(line: &str, col: usize) -> usize {
+    let units: usize = line.chars().take(col).map(char::len_utf16).sum();
+    units + col.saturating_sub(line.chars().count())
+}
+fn utf16_to_char(line: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (col, c) in line.chars().enumerate() {
+        if units >= character {
+            return col;
+        }
+        units += c.len_utf16();
+    }
+    line.chars().count() + character.saturating_sub(units)
+}
+impl Display for Position {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ln {}..{}, col {}..{}", self.ln.start, self.ln.end, self.col.start, self.col.end)
     }
 }
 impl<T> Located<T> {
@@ -28,15 +98,37 @@ impl<T> Located<T> {
     pub fn unwrap(self) -> T {
         self.value
     }
+    /// Debug formatter that appends `@ ln .., col ..` to this and every nested `Located` value
+    pub fn debug_with_pos(&self) -> DebugWithPos<'_, T> {
+        DebugWithPos(self)
+    }
 }
 impl<T: Debug> Debug for Located<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.value.fmt(f)
+        self.value.fmt(f)?;
+        if DEBUG_POS.with(Cell::get) {
+            write!(f, " @ {}", self.pos)?;
+        }
+        Ok(())
     }
 }
+impl<T: Debug> Debug for DebugWithPos<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let prev = DEBUG_POS.with(|flag| flag.replace(true));
+        let result = self.0.fmt(f);
+        DEBUG_POS.with(|flag| flag.set(prev));
+        result
+    }
+}
+/// Prefixes the value with its one-based starting line and column, like `2:5: message`
 impl<T: Display> Display for Located<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        self.value.fmt(f)
+        write!(f, "{}:{}: {}", self.pos.ln.start + 1, self.pos.col.start + 1, self.value)
+    }
+}
+impl<T: Error + 'static> Error for Located<T> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.value.source()
     }
 }
 impl<T: Clone> Clone for Located<T> {
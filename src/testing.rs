@@ -0,0 +1,109 @@
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::{
+    lexer::{Lexer, Token},
+    parser::{Parsable, ParseError, Parser, Program},
+    position::{Located, Position},
+};
+
+/// Tokens substituted into programs by `mutate`, one per token kind
+pub fn replacements() -> Vec<Token> {
+    vec![
+        Token::Ident("x".to_string()),
+        Token::Integer(0),
+        Token::Decimal(0.5),
+        Token::String("s".to_string()),
+        Token::Bool(true),
+        Token::Null,
+        Token::ParanLeft,
+        Token::ParanRight,
+        Token::BracketLeft,
+        Token::BracketRight,
+        Token::BraceLeft,
+        Token::BraceRight,
+        Token::Equal,
+        Token::Semicolon,
+        Token::Dot,
+        Token::Pipe,
+        Token::Comma,
+        Token::Plus,
+        Token::Minus,
+        Token::Star,
+        Token::Slash,
+        Token::Percent,
+        Token::EqualEqual,
+        Token::ExclamationEqual,
+        Token::Less,
+        Token::LessEqual,
+        Token::Greater,
+        Token::GreaterEqual,
+        Token::Exclamation,
+        Token::If,
+        Token::Else,
+        Token::While,
+        Token::Fn,
+        Token::For,
+        Token::In,
+        Token::Break,
+        Token::Continue,
+        Token::Return,
+        Token::Ellipsis,
+    ]
+}
+
+/// Every token stream obtained by deleting one token or replacing it with one of `replacements`
+pub fn mutate(tokens: &[Located<Token>]) -> Vec<Vec<Located<Token>>> {
+    let replacements = replacements();
+    let mut mutations = vec![];
+    for idx in 0..tokens.len() {
+        let mut deleted = tokens.to_vec();
+        deleted.remove(idx);
+        mutations.push(deleted);
+        for token in replacements.iter() {
+            if token == &tokens[idx].value {
+                continue;
+            }
+            let mut replaced = tokens.to_vec();
+            replaced[idx].value = token.clone();
+            mutations.push(replaced);
+        }
+    }
+    mutations
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Failure {
+    Panicked(Vec<Located<Token>>),
+    InvalidSpan(Vec<Located<Token>>, Located<ParseError>),
+}
+
+/// Parses every mutation of `source`, returning those where the parser panicked
+/// or reported an error positioned outside of the source
+pub fn check_mutations(source: &str) -> Result<Vec<Failure>, String> {
+    let tokens = Lexer::new(source)
+        .lex()
+        .map_err(|err| format!("{:?}", err.debug_with_pos()))?;
+    let lines = source.split('\n').count();
+    let mut failures = vec![];
+    for mutation in mutate(&tokens) {
+        let parsed = panic::catch_unwind(AssertUnwindSafe(|| {
+            Program::parse(&mut Parser::new(mutation.clone()))
+        }));
+        match parsed {
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => {
+                if !valid_span(&err.pos, lines) {
+                    failures.push(Failure::InvalidSpan(mutation, err));
+                }
+            }
+            Err(_) => failures.push(Failure::Panicked(mutation)),
+        }
+    }
+    Ok(failures)
+}
+
+fn valid_span(pos: &Position, lines: usize) -> bool {
+    pos.ln.start <= pos.ln.end
+        && pos.ln.end < lines
+        && (pos.ln.start != pos.ln.end || pos.col.start <= pos.col.end)
+}
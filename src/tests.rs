@@ -1,4 +1,5 @@
-use crate::{lexer::{LexError, Lexer, Token}, parser::{Parsable, Program}, position::Located};
+use crate::{compiler::{self, CompileError}, grammar, interpreter::{value::Value, Interpreter, Limit, Limits, RuntimeError}, ir::{cfg::{self, Cfg}, optimize::{self, OptLevel}, Closure, IRCompiler, LabeledIR, LinkError, IR}, lexer::{LexError, Lexer, LexerLimits, Token}, parser::{self, Atom, BinaryOperator, Expression, Parsable, ParseError, Parser, Path, Program, Statement, UnaryOperator}, position::{Located, LspPosition, Position}, testing, token_stream, CancelToken, Error};
+use std::sync::Arc;
 
 #[test]
 fn lexing_hello_world() -> Result<(), Located<LexError>> {
@@ -19,8 +20,1071 @@ fn main() {
     let text = r#"a.1 = 2;"#;
     let tokens = Lexer::new(text).lex().unwrap();
     dbg!(&tokens);
-    let ast = Program::parse(&mut tokens.into_iter().peekable()).unwrap();
+    let ast = Program::parse(&mut Parser::new(tokens)).unwrap();
     dbg!(&ast);
     // let ir = .unwrap();
     // dbg!(&ir);
-}
\ No newline at end of file
+}
+#[test]
+fn program_metrics() {
+    let text = "a = f(1, [2.5 \"s\"]);\nprint(a.b);";
+    let tokens = Lexer::new(text).lex().unwrap();
+    let ast = Program::parse(&mut Parser::new(tokens)).unwrap();
+    let metrics = ast.value.metrics(text);
+    assert_eq!(metrics.statements, 2);
+    assert_eq!(metrics.calls, 2);
+    assert_eq!(metrics.integers, 1);
+    assert_eq!(metrics.decimals, 1);
+    assert_eq!(metrics.strings, 1);
+    assert_eq!(metrics.max_depth, 3);
+    assert_eq!(&text[metrics.span], "a = f(1, [2.5 \"s\"]);\nprint(a.b)");
+}
+
+#[test]
+fn extending_positions() {
+    let mut pos = crate::position::Position::new(0..0, 4..7);
+    pos.extend(&crate::position::Position::new(0..0, 10..12));
+    assert_eq!(pos, crate::position::Position::new(0..0, 4..12));
+    pos.extend(&crate::position::Position::new(2..2, 8..9));
+    assert_eq!(pos, crate::position::Position::new(0..2, 4..9));
+}
+
+#[test]
+fn debug_with_positions() {
+    let text = "print(ab);";
+    let tokens = Lexer::new(text).lex().unwrap();
+    let ast = Program::parse(&mut Parser::new(tokens)).unwrap();
+    let debug = format!("{:?}", ast.debug_with_pos());
+    assert!(debug.contains("Atom(Path(Ident(\"ab\"))) @ ln 0..0, col 6..8"));
+    assert!(!format!("{:?}", ast).contains('@'));
+}
+
+#[test]
+fn lsp_range_roundtrip() {
+    let text = "a = 1;\nb = '😀x';";
+    let pos = Position::new(1..1, 5..8);
+    let range = pos.to_lsp_range(text);
+    assert_eq!(range.start, LspPosition { line: 1, character: 5 });
+    assert_eq!(range.end, LspPosition { line: 1, character: 9 });
+    assert_eq!(Position::from_lsp_range(&range, text), pos);
+    assert_eq!(&text[pos.byte_range(text)], "😀x'");
+}
+
+#[test]
+fn lexer_limits() {
+    let limits = LexerLimits {
+        max_string_len: 4,
+        max_ident_len: 3,
+        max_tokens: 5,
+    };
+    let lex = |text| Lexer::new(text).with_limits(limits.clone()).lex().map_err(Located::unwrap);
+    assert!(lex(r#"f("abcd");"#).is_ok());
+    assert_eq!(lex(r#"f("abcde");"#), Err(LexError::StringTooLong));
+    assert_eq!(lex("abcd;"), Err(LexError::IdentTooLong));
+    assert_eq!(lex("a = 1; b;"), Err(LexError::TooManyTokens));
+}
+
+#[test]
+fn cancellation() {
+    let cancel = CancelToken::new();
+    let tokens = Lexer::new("a = 1;").with_cancel(cancel.clone()).lex().unwrap();
+    cancel.cancel();
+    assert_eq!(
+        Lexer::new("a = 1;").with_cancel(cancel.clone()).lex().map_err(Located::unwrap),
+        Err(LexError::Cancelled)
+    );
+    assert_eq!(
+        Program::parse(&mut Parser::new(tokens.clone()).with_cancel(cancel.clone())).map_err(Located::unwrap),
+        Err(ParseError::Cancelled)
+    );
+    let tokens = Lexer::new("f(g(1) + 2)").lex().unwrap();
+    let mut parser = Parser::new(tokens).with_cancel(cancel.clone());
+    assert_eq!(Expression::parse(&mut parser).map_err(Located::unwrap), Err(ParseError::Cancelled));
+    let ast = Program::parse(&mut Parser::new(Lexer::new("if true { a = [1]; }").lex().unwrap())).unwrap();
+    assert_eq!(
+        compiler::compile_with(&ast, OptLevel::None, IRCompiler::new().with_cancel(cancel)).map_err(Located::unwrap),
+        Err(CompileError::Cancelled)
+    );
+}
+
+#[test]
+fn closure_is_send_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Closure>();
+
+    // one compiled program shared by interpreters on several threads
+    let closure = Arc::new(crate::compile("fn f(n) { return n * 2; } return f(21);").unwrap());
+    let workers: Vec<_> = (0..2).map(|_| {
+        let closure = Arc::clone(&closure);
+        std::thread::spawn(move || matches!(Interpreter::new().run(&closure), Ok(Value::Int(42))))
+    }).collect();
+    for worker in workers {
+        assert!(worker.join().unwrap());
+    }
+}
+
+#[test]
+fn pipe_desugars_to_calls() {
+    assert_eq!(program("a = x |> f |> g(1);"), program("a = g(f(x) 1);"));
+}
+
+#[cfg(feature = "js")]
+#[test]
+fn transpile_to_js() {
+    let text = "a.b = f(1, 2.0, ['say \"hi\"']);\nprint(a.1, [new], a[0]);";
+    let tokens = Lexer::new(text).lex().unwrap();
+    let ast = Program::parse(&mut Parser::new(tokens)).unwrap();
+    assert_eq!(
+        crate::js::transpile(&ast.value),
+        "a.b = f(1, 2.0, [\"say \\\"hi\\\"\"]);\nprint(a[1], [_new], a[0]);\n"
+    );
+    // names first assigned in a function or block are declared, also when assigned in a branch
+    let js = crate::js::transpile(&program("x = 1; f = fn() { x = 2; }; f(); fn g(c) { if c { r = 1; } { r = 2; q = r; } return r; }").value);
+    assert_eq!(
+        js,
+        "x = 1;\nf = (function() {\n    let x;\n    x = 2;\n});\nf();\nfunction g(c) {\n    let r;\n    if (c) {\n        r = 1;\n    }\n    {\n        let q;\n        r = 2;\n        q = r;\n    }\n    return r;\n}\n"
+    );
+    assert_eq!(crate::js::transpile(&program("{ fn f() {} }").value), "{\n    let f;\n    f = function() {\n    };\n}\n");
+    // a top-level return is only valid JavaScript inside a function
+    assert_eq!(crate::js::transpile(&program("x = 1; if x { return x; }").value), "(function() {\n    x = 1;\n    if (x) {\n        return x;\n    }\n})();\n");
+    assert_eq!(crate::js::transpile(&program("fn f() { return 1; }").value), "function f() {\n    return 1;\n}\n");
+}
+
+#[test]
+fn token_stream_roundtrip() {
+    let text = "a.b = f(1 2.5 'x' |> g);\n# comment\n{[]};";
+    let tokens = Lexer::new(text).lex().unwrap();
+    let bytes = token_stream::encode(&tokens);
+    let decoded = token_stream::decode(&bytes).unwrap();
+    assert_eq!(decoded, tokens);
+    assert!(decoded.iter().zip(&tokens).all(|(a, b)| a.pos == b.pos));
+    assert_eq!(token_stream::decode(&bytes[..bytes.len() - 1]), Err(token_stream::DecodeError::UnexpectedEnd));
+}
+
+#[test]
+fn lexing_escapes() -> Result<(), Located<LexError>> {
+    let tokens = Lexer::new(r#"f("a\n\"b\65" 'c');"#).lex_checked()?;
+    let tokens: Vec<Token> = tokens.into_iter().map(Located::unwrap).collect();
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Ident("f".to_string()),
+            Token::ParanLeft,
+            Token::String("a\n\"bA".to_string()),
+            Token::String("c".to_string()),
+            Token::ParanRight,
+            Token::Semicolon,
+        ]
+    );
+    Ok(())
+}
+
+/// xorshift so the fuzz inputs are reproducible without extra dependencies
+fn fuzz_rng(seed: &mut u64) -> u64 {
+    *seed ^= *seed << 13;
+    *seed ^= *seed >> 7;
+    *seed ^= *seed << 17;
+    *seed
+}
+
+#[test]
+fn lexing_fuzz_no_panic() {
+    let alphabet: Vec<char> = "ab1 9.\"'\\#\n\t()[]{}=;.|>_é😀\r0".chars().collect();
+    let mut seed = 0x2545_f491_4f6c_dd1d;
+    for _ in 0..20_000 {
+        let len = (fuzz_rng(&mut seed) % 24) as usize;
+        let text: String = (0..len)
+            .map(|_| alphabet[(fuzz_rng(&mut seed) % alphabet.len() as u64) as usize])
+            .collect();
+        let lines = text.split('\n').count();
+        match Lexer::new(&text).lex_checked() {
+            Ok(tokens) => assert!(tokens.iter().all(|token| token.pos.ln.end < lines)),
+            Err(err) => assert!(err.pos.ln.end < lines, "{text:?}: {err:?}"),
+        }
+    }
+}
+
+#[test]
+fn lexing_fuzz_no_truncation() {
+    let pieces = [
+        "x", "abc", "12", "3.5", "'s'", "\"t\\n\"", "(", ")", "[", "]", "{", "}", "=", ";", ".",
+        "|>", "# note\n",
+    ];
+    let mut seed = 0x9e37_79b9_7f4a_7c15;
+    for _ in 0..5_000 {
+        let len = (fuzz_rng(&mut seed) % 16) as usize;
+        let picked: Vec<&str> = (0..len)
+            .map(|_| pieces[(fuzz_rng(&mut seed) % pieces.len() as u64) as usize])
+            .collect();
+        let text = picked.join(" ");
+        let tokens = Lexer::new(&text).lex_checked().unwrap();
+        let expected = picked.iter().filter(|piece| !piece.starts_with('#')).count();
+        assert_eq!(tokens.len(), expected, "{text:?}");
+    }
+}
+
+#[test]
+fn parse_complete_rejects_trailing_tokens() {
+    let tokens = Lexer::new("f(1) 2\n3").lex().unwrap();
+    let err = Expression::parse_complete(&mut Parser::new(tokens)).unwrap_err();
+    assert_eq!(err.value, ParseError::TrailingTokens);
+    assert_eq!(err.pos.ln, 0..1);
+    assert_eq!((err.pos.col.start, err.pos.col.end), (5, 1));
+    let tokens = Lexer::new("f(1)").lex().unwrap();
+    assert!(Expression::parse_complete(&mut Parser::new(tokens)).is_ok());
+}
+
+#[test]
+fn grammar_matches_lexer() {
+    for kind in grammar::TOKENS.iter().filter(|kind| kind.literal) {
+        let tokens = Lexer::new(kind.pattern).lex().unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].value.kind(), kind.name);
+    }
+    let tokens = Lexer::new("a 1 2.5 'x'").lex().unwrap();
+    assert!(tokens.iter().all(|token| grammar::token_kind(token.value.kind()).is_some()));
+    let ebnf = grammar::ebnf();
+    assert!(ebnf.starts_with("program = { statement } ;\n"));
+    for operator in grammar::BINARY_OPERATORS {
+        let tokens = Lexer::new(operator.symbol).lex().unwrap();
+        let op = BinaryOperator::from_token(&tokens[0].value).unwrap();
+        assert_eq!(op.precedence(), operator.precedence);
+    }
+    for production in grammar::PRODUCTIONS {
+        for name in production.alternatives.iter().flat_map(|alt| alt.split_whitespace()) {
+            if name.chars().all(|c| c.is_ascii_alphabetic()) {
+                assert!(grammar::production(name).is_some() || grammar::token_kind(name).is_some(), "{name}");
+            }
+        }
+    }
+}
+
+#[test]
+fn mutated_programs_fail_cleanly() {
+    let sources = [
+        "print(\"hello\");",
+        "a.b = f(1, [2.5 'x'] (g));\nh(a.1 |> k(2));",
+        "a[i + 1] = f(x)[0][1];",
+        "m = { a = 1; 'b' = [2, 3] };",
+        "x = a + b * (c - 1) <= 2 % d;",
+        "f(-a, !b);",
+        "if a { f(1); } else if b {} else { g(); }",
+        "while a < 3 { a = a + 1; }",
+        "for x in [1, 2] { f(x); }",
+        "while a { if b { break; } continue; }",
+        "fn f(x) { if x { return; } return x + 1; }",
+        "{ a = 1; { f(a); } }",
+        "print(list.get(0).name[1](x).2);",
+        "f(1, x = 2, y = g(z = 3));",
+        "f(...[1, ...xs], 2, k = 3);",
+        "fn f(a, b) { g(a); }",
+        "h(fn(x) { g(x); });",
+    ];
+    for source in sources {
+        let failures = testing::check_mutations(source).unwrap();
+        assert_eq!(failures, vec![], "{source}");
+    }
+}
+
+#[test]
+fn comma_separated_arguments() {
+    let parse = |text| parse_program(text).map_err(Located::unwrap);
+    assert_eq!(parse("f(1, 2);"), parse("f(1 2);"));
+    assert_eq!(parse("f(1, [2, 3,],);"), parse("f(1, [2 3]);"));
+    assert_eq!(parse("f(1, 2 3);"), Err(ParseError::MissingComma { got: Token::Integer(3) }));
+    assert_eq!(parse("f(1 2, 3);"), Err(ParseError::UnexpectedComma));
+    assert_eq!(parse("f(, 1);"), Err(ParseError::UnexpectedComma));
+    assert_eq!(parse("f(1,, 2);"), Err(ParseError::UnexpectedComma));
+    assert_eq!(parse("a = [1 -2];"), Err(ParseError::AmbiguousMinus));
+    assert_eq!(parse("f(a -b);"), Err(ParseError::AmbiguousMinus));
+    assert_eq!(parse("f(x = a -b c);"), Err(ParseError::AmbiguousMinus));
+    assert_eq!(parse("f(a, b -c);"), parse("f(a, b - c);"));
+    assert_eq!(parse("f(n -1, x);"), parse("f(n - 1, x);"));
+    assert_eq!(parse("a = [n -1, 2];"), parse("a = [n - 1, 2];"));
+    assert_eq!(parse("f(x = a -b, c);"), parse("f(x = a - b, c);"));
+    assert_eq!(parse("f(a -b c);"), Err(ParseError::AmbiguousMinus));
+    assert_eq!(parse("a = [x[y -z] -1];"), Err(ParseError::AmbiguousMinus));
+    assert_eq!(parse("a = [x -f(y)];"), Err(ParseError::AmbiguousMinus));
+    assert_eq!(parse("a = [1 - 2];"), parse("a = [1-2];"));
+    assert_eq!(parse("a = [(1 -2) x[y -z]];"), parse("a = [(1 - 2), x[y - z]];"));
+    assert_eq!(parse("a = [[x -y]];"), Err(ParseError::AmbiguousMinus));
+    assert_eq!(parse("a = b -c;"), parse("a = b - c;"));
+}
+
+#[test]
+fn parsing_maps() {
+    let parse = |text| {
+        let tokens = Lexer::new(text).lex().unwrap();
+        Expression::parse_complete(&mut Parser::new(tokens)).map(Located::unwrap).map_err(Located::unwrap)
+    };
+    assert_eq!(parse("{}"), Ok(Expression::Atom(Atom::Map(vec![]))));
+    let Ok(Expression::Atom(Atom::Map(pairs))) = parse("{ a = 1; 'b c' = { d = [] }; }") else {
+        panic!("expected a map");
+    };
+    assert_eq!(pairs.len(), 2);
+    assert_eq!(pairs[0].0.value, "a");
+    assert_eq!(pairs[1].0.value, "b c");
+    assert!(matches!(&pairs[1].1.value, Expression::Atom(Atom::Map(inner)) if inner.len() == 1));
+    assert_eq!(parse("{ a = 1 }"), parse("{ a = 1; }"));
+    assert_eq!(
+        parse("{ a = 1 b = 2 }"),
+        Err(ParseError::ExpectedTokens { expected: &[Token::Semicolon, Token::BraceRight], got: Token::Ident("b".to_string()) })
+    );
+}
+
+/// Lexes and parses `text`, which must lex
+fn parse_program(text: &str) -> Result<Located<Program>, Located<ParseError>> {
+    let tokens = Lexer::new(text).lex().unwrap();
+    Program::parse(&mut Parser::new(tokens))
+}
+fn program(text: &str) -> Located<Program> {
+    parse_program(text).unwrap()
+}
+fn expression(text: &str) -> Located<Expression> {
+    let tokens = Lexer::new(text).lex().unwrap();
+    Expression::parse_complete(&mut Parser::new(tokens)).unwrap()
+}
+
+/// Fully parenthesized rendering of an expression, ignoring grouping parentheses of the source
+fn sexpr(expr: &Expression) -> String {
+    match expr {
+        Expression::Atom(Atom::Expression(expr)) => sexpr(&expr.value),
+        Expression::Atom(Atom::Path(Path::Ident(ident))) => ident.clone(),
+        Expression::Atom(atom) => format!("{atom:?}"),
+        Expression::Call { head, args } => format!(
+            "{}({})",
+            sexpr(&head.value),
+            args.iter()
+                .map(|(name, arg)| match name {
+                    Some(name) => format!("{}={}", name.value, sexpr(&arg.value)),
+                    None => sexpr(&arg.value),
+                })
+                .collect::<Vec<String>>()
+                .join(" ")
+        ),
+        Expression::Binary { op, left, right } => {
+            format!("({} {:?} {})", sexpr(&left.value), op, sexpr(&right.value))
+        }
+        Expression::Unary { op, expr } => format!("({:?} {})", op, sexpr(&expr.value)),
+        Expression::Function { params, body } => format!("fn({}) {{{}}}", params.len(), body.len()),
+        Expression::Field { head, field } => match &field.value {
+            Atom::Path(Path::Ident(ident)) => format!("{}.{}", sexpr(&head.value), ident),
+            atom => format!("{}.{:?}", sexpr(&head.value), atom),
+        },
+        Expression::Spread(expr) => format!("...{}", sexpr(&expr.value)),
+        Expression::Index { head, index } => format!("{}[{}]", sexpr(&head.value), sexpr(&index.value)),
+    }
+}
+
+#[test]
+fn binary_precedence() {
+    let shape = |text| sexpr(&expression(text).value);
+    assert_eq!(shape("a + b * c"), "(a Add (b Mul c))");
+    assert_eq!(shape("a - b - c"), "((a Sub b) Sub c)");
+    assert_eq!(shape("a * b % c <= d + e"), "(((a Mul b) Mod c) Le (d Add e))");
+    assert_eq!(shape("a == b != c"), "((a Eq b) Ne c)");
+    assert_eq!(shape("(a + b) * c"), "((a Add b) Mul c)");
+    assert_eq!(shape("1 + x |> f"), "f((Integer(1) Add x))");
+    let expr = expression("f(x) >= 2.5");
+    assert_eq!(expr.pos, Position::new(0..0, 0..11));
+    assert!(matches!(expr.value, Expression::Binary { op: BinaryOperator::Ge, .. }));
+}
+
+#[test]
+fn unary_operators() {
+    let shape = |text| sexpr(&expression(text).value);
+    assert_eq!(shape("-a * b"), "((Neg a) Mul b)");
+    assert_eq!(shape("a - -b"), "(a Sub (Neg b))");
+    assert_eq!(shape("!f(x) == !!y"), "((Not f(x)) Eq (Not (Not y)))");
+    assert_eq!(shape("-1 != 2"), "((Neg Integer(1)) Ne Integer(2))");
+    let expr = expression("-f(x)");
+    assert_eq!(expr.pos, Position::new(0..0, 0..5));
+    assert!(matches!(expr.value, Expression::Unary { op: UnaryOperator::Neg, .. }));
+}
+
+#[test]
+fn bool_and_null_literals() -> Result<(), Located<LexError>> {
+    let tokens = Lexer::new("a = [true false null nullable];").lex()?;
+    let tokens: Vec<Token> = tokens.into_iter().map(Located::unwrap).collect();
+    assert_eq!(tokens[3..7], [Token::Bool(true), Token::Bool(false), Token::Null, Token::Ident("nullable".to_string())]);
+    let tokens = Lexer::new("!true == null").lex()?;
+    let expr = Expression::parse_complete(&mut Parser::new(tokens)).unwrap();
+    assert_eq!(sexpr(&expr.value), "((Not Bool(true)) Eq Null)");
+    Ok(())
+}
+
+#[test]
+fn compiling_if_else() {
+    let ast = program("if x { a = 1; } else { a = 2; }");
+    let mut compiler = IRCompiler::new();
+    ast.compile(&mut compiler).unwrap();
+    let closure = compiler.pop_closure().unwrap();
+    let code: Vec<(IR, Option<usize>)> = closure
+        .code
+        .into_iter()
+        .map(|ir| (ir.value.ir, ir.value.label))
+        .collect();
+    assert_eq!(
+        code,
+        vec![
+            (IR::Get { dst: 0, addr: 0 }, None),
+            (IR::JumpIf { negative: true, cond: 0, addr: 1 }, None),
+            (IR::Int { dst: 1, addr: 0 }, None),
+            (IR::Set { addr: 1, src: 1 }, None),
+            (IR::Jump { addr: 0 }, None),
+            (IR::None, Some(1)),
+            (IR::Int { dst: 1, addr: 1 }, None),
+            (IR::Set { addr: 1, src: 1 }, None),
+            (IR::None, Some(0)),
+        ]
+    );
+}
+
+#[test]
+fn compiling_nested_while() {
+    assert_eq!(
+        run("n = 0; i = 0; while i < 3 { j = 0; while j < 2 { n = n + 1; j = j + 1; } i = i + 1; } return n;"),
+        Ok(Value::Int(6))
+    );
+}
+
+#[test]
+fn compiling_functions() {
+    let closure = compile("fn add(a, b) { return a + b; } add(1 2);").unwrap();
+    assert_eq!(closure.string[0], "add");
+    assert_eq!(closure.closures[0].params, 2);
+    assert_eq!(run("fn add(a, b) { r = a; a = b; return [a, r]; } return add(1 2);"), run("return [2, 1];"));
+    assert_eq!(
+        compile("fn outer(a) { return fn() { return a; }; }").map(|_| ()),
+        Err(Located::new(CompileError::CapturedLocal("a".to_string()), Position::new(0..0, 35..36)))
+    );
+    assert_eq!(
+        compile("{ x = 1; f = fn() { return x + 1; }; }").map(|_| ()).map_err(Located::unwrap),
+        Err(CompileError::CapturedLocal("x".to_string()))
+    );
+    assert_eq!(run("x = 1; fn f(y) { return fn() { x = 2; return x; }; } return f(0)();"), Ok(Value::Int(2)));
+}
+
+#[test]
+fn lambdas_as_arguments() {
+    let ast = program("map(xs, fn(x) { print(x); });");
+    let Statement::Call { head: _, args } = &ast.value.0[0].value else {
+        panic!("expected a call");
+    };
+    assert_eq!(sexpr(&args[1].1.value), "fn(1) {1}");
+    assert_eq!(args[1].1.pos, Position::new(0..0, 8..27));
+    assert_eq!(run("fn apply(f, x) { return f(x); } return apply(fn(x) { return x * 2; }, 21);"), Ok(Value::Int(42)));
+}
+
+#[test]
+fn indexing() {
+    let ast = program("xs[0] = f(x)[i];");
+    let Statement::Assign { path, expr } = &ast.value.0[0].value else {
+        panic!("expected an assignment");
+    };
+    assert!(matches!(path.value, Path::Index { .. }));
+    assert_eq!(path.pos, Position::new(0..0, 0..5));
+    assert_eq!(sexpr(&expr.value), "f(x)[i]");
+    assert_eq!(expr.pos, Position::new(0..0, 8..15));
+    assert_eq!(run("fn f(x) { return x; } xs = [0, 0]; i = 1; xs[0] = f([5, 6])[i]; return xs;"), run("return [6, 0];"));
+}
+
+#[test]
+fn compiling_field_assignment() {
+    assert_eq!(run("a = {}; a.b = 1; a.c = { d = 2; }; return [a.b, a.c.d];"), run("return [1, 2];"));
+    assert_eq!(run("a = [0, 1]; a.1 = 5; return a;"), run("return [0, 5];"));
+}
+
+#[test]
+fn compiling_for_loops() {
+    let ast = program("for x in xs { f(x); } g(x);");
+    assert_eq!(ast.value.0[0].pos, Position::new(0..0, 0..21));
+    let mut compiler = IRCompiler::new();
+    ast.compile(&mut compiler).unwrap();
+    let closure = compiler.pop_closure().unwrap();
+    let code: Vec<(IR, Option<usize>)> = closure
+        .code
+        .into_iter()
+        .map(|ir| (ir.value.ir, ir.value.label))
+        .collect();
+    assert_eq!(
+        code,
+        vec![
+            (IR::Get { dst: 0, addr: 0 }, None),
+            (IR::IterInit { dst: 1, src: 0 }, None),
+            (IR::None, Some(0)),
+            (IR::IterNext { dst: 2, iter: 1, addr: 1 }, None),
+            (IR::Get { dst: 3, addr: 1 }, None),
+            (IR::Move { dst: 4, src: 2 }, None),
+            (IR::Call { dst: None, func: 3, start: 4, amount: 1 }, None),
+            (IR::Jump { addr: 0 }, None),
+            (IR::None, Some(1)),
+            (IR::Get { dst: 0, addr: 2 }, None),
+            (IR::Get { dst: 2, addr: 3 }, None),
+            (IR::Move { dst: 1, src: 2 }, None),
+            (IR::Call { dst: None, func: 0, start: 1, amount: 1 }, None),
+        ]
+    );
+    assert_eq!(closure.string, vec!["xs", "f", "g", "x"]);
+}
+
+#[test]
+fn break_and_continue() {
+    assert_eq!(
+        run("s = 0; for x in [1, 2, 3] { i = 0; while true { i = i + 1; if i > x { break; } } if x == 2 { continue; } s = s + i; } return s;"),
+        Ok(Value::Int(6))
+    );
+    assert_eq!(
+        compile("if a { break; }").map(|_| ()),
+        Err(Located::new(CompileError::BreakOutsideLoop, Position::new(0..0, 7..13)))
+    );
+    assert_eq!(
+        compile("while a { f(fn() { continue; }); }").map(|_| ()).map_err(Located::unwrap),
+        Err(CompileError::ContinueOutsideLoop)
+    );
+}
+
+#[test]
+fn return_statements() {
+    let ast = program("fn f(x) { return x; } return f(1);");
+    let Statement::Function { body, .. } = &ast.value.0[0].value else {
+        panic!("expected a function");
+    };
+    assert_eq!(body[0].pos, Position::new(0..0, 10..19));
+    assert_eq!(run("fn f(x) { return x; } return f(1);"), Ok(Value::Int(1)));
+    assert_eq!(run("fn g() { return; h(); } return g();"), Ok(Value::Null));
+    assert_eq!(program("return;").value.0[0].value, Statement::Return(None));
+}
+
+#[test]
+fn block_scopes() {
+    let ast = program("{ for x in xs {} { f(x); } }");
+    assert!(matches!(&ast.value.0[0].value, Statement::Block(stats) if stats.len() == 2));
+    assert_eq!(run("x = 1; { x = 2; } return x;"), Ok(Value::Int(1)));
+    assert_eq!(run("{ y = 1; } return y;"), Err(RuntimeError::UndefinedGlobal("y".to_string())));
+    assert_eq!(run("{ y = 1; { y = y + 1; z = y; } if y == 2 { return z; } }"), Err(RuntimeError::UndefinedGlobal("z".to_string())));
+    assert_eq!(run("x = 0; for x in [5] {} return x;"), Ok(Value::Int(0)));
+    assert_eq!(run("fn f() { x = 1; { x = 2; y = 3; } return x; } return f();"), Ok(Value::Int(2)));
+    assert_eq!(run("fn f() { { a = 1; } { b = [2]; c = b; } d = 3; return d; } return f();"), Ok(Value::Int(3)));
+    assert_eq!(run("f = fn(c) { if c { r = 1; } else { r = 2; } return r; }; return [f(true), f(false)];"), run("return [1, 2];"));
+    assert_eq!(run("f = fn() { i = 0; while i < 3 { last = i; i = i + 1; } return last; }; return f();"), Ok(Value::Int(2)));
+    assert_eq!(run("fn f(l) { for x in l { y = x; } return y; } return f([1, 2]);"), Ok(Value::Int(2)));
+    assert_eq!(run("fn f() { { if true { a = 1; } } return a; } return f();"), Err(RuntimeError::UndefinedGlobal("a".to_string())));
+}
+
+#[test]
+fn postfix_chains() {
+    let expr = expression("list.get(0).name");
+    assert!(matches!(&expr.value, Expression::Field { head, .. } if matches!(head.value, Expression::Call { .. })));
+    assert_eq!(expr.pos, Position::new(0..0, 0..16));
+    assert_eq!(sexpr(&expression("f()[0].a.1(x)").value), "f()[Integer(0)].a.Integer(1)(x)");
+    assert_eq!(sexpr(&expression("-b().c").value), "(Neg b().c)");
+    assert_eq!(
+        run("l = [{ name = \"a\"; }]; list = { get = fn(i) { return l[i]; }; }; x = list.get(0).name; return x;"),
+        Ok(Value::String("a".to_string()))
+    );
+    let ast = program("x.get(0).push(1); a.b(c).d = 1; a[0].b = 2;");
+    assert!(matches!(&ast.value.0[0].value, Statement::Call { head, .. } if matches!(head.value, Expression::Field { .. })));
+    assert!(matches!(&ast.value.0[1].value, Statement::Assign { path, .. } if matches!(&path.value, Path::Field { head, .. } if matches!(head.value, Expression::Call { .. }))));
+    assert_eq!(ast.value.0[1].pos, Position::new(0..0, 18..30));
+    let log = "log = []; x = { get = fn(i) { return { push = fn(v) { push(log, v); }; }; }; };";
+    assert_eq!(run_with(&mut Interpreter::with_stdlib(), &format!("{log} x.get(0).push(1); return log;")), run("return [1];"));
+    assert_eq!(run("m = {}; a = { b = fn(c) { return c; }; }; a.b(m).d = 1; return m.d;"), Ok(Value::Int(1)));
+    assert_eq!(parse_program("f() = 1;").map_err(Located::unwrap), Err(ParseError::NotAssignable));
+    assert_eq!(
+        parse_program("a.b(c).d;").map_err(Located::unwrap),
+        Err(ParseError::ExpectedTokens { expected: &[Token::Equal, Token::ParanLeft], got: Token::Semicolon })
+    );
+}
+
+#[test]
+fn keyword_arguments() {
+    assert_eq!(sexpr(&expression("f(x = 1 y = 2)").value), "f(x=Integer(1) y=Integer(2))");
+    assert_eq!(sexpr(&expression("f(a, b = a == c)").value), "f(a b=(a Eq c))");
+    assert_eq!(sexpr(&expression("x |> f(n = 1)").value), "f(x n=Integer(1))");
+    #[cfg(feature = "js")]
+    assert_eq!(crate::js::transpile(&program("f(1, x = 2);").value), "f(1, {\"x\": 2});\n");
+    assert_eq!(run("fn f(a, kw) { return [a, kw.x, kw.y]; } return f(1, x = 2, y = 3);"), run("return [1, 2, 3];"));
+}
+
+#[test]
+fn spread_arguments() {
+    assert_eq!(sexpr(&expression("f(...xs, 1)").value), "f(...xs Integer(1))");
+    let expr = expression("[0 ...xs.ys]");
+    let Expression::Atom(Atom::List(items)) = &expr.value else {
+        panic!("expected a list");
+    };
+    assert_eq!(items[1].pos, Position::new(0..0, 3..11));
+    assert_eq!(run("fn f(a, b, c) { return [a, b, c]; } xs = [1, 2]; return f(...xs, 3);"), run("return [1, 2, 3];"));
+    assert_eq!(run("xs = [1, 2]; return [0, ...xs, ...[], 3];"), run("return [0, 1, 2, 3];"));
+    assert_eq!(run("fn f(a, kw) { return [a, kw.k]; } return f(...[1], k = 2);"), run("return [1, 2];"));
+    // arguments are evaluated in source order, keyword arguments included
+    let order = "log = []; fn g(x) { push(log, x); return x; } fn f(a, b, kw) { return [a, b, kw.k, log]; }";
+    let run = |text| run_with(&mut Interpreter::with_stdlib(), &format!("{order} {text}"));
+    assert_eq!(run("return f(...[g(1)], k = g(2), g(3));"), run("return [1, 3, 2, [1, 2, 3]];"));
+    assert_eq!(run("return f(g(1), k = g(2), g(3));"), run("return [1, 3, 2, [1, 2, 3]];"));
+}
+
+#[test]
+fn compiling_programs() {
+    let closure = compile("fn f(xs, k) { for x in xs { if x { return x[k]; } } }\ny = f([a.b, ...c], k = { n = 1; });").unwrap();
+    assert_eq!(closure.code.last().unwrap().value.ir, IR::Return { src: None });
+    assert_eq!(closure.closures.len(), 1);
+    assert_eq!(closure.closures[0].params, 2);
+    assert_eq!(
+        compile("while x {}\nif y { continue; }"),
+        Err(Located::new(CompileError::ContinueOutsideLoop, Position::new(1..1, 7..16)))
+    );
+}
+
+#[test]
+fn resolving_labels() {
+    let mut closure = compile("while a { if b { break; } f(fn() { while c {} }); }").unwrap();
+    closure.resolve_labels().unwrap();
+    let code: Vec<(IR, Option<usize>)> = closure
+        .code
+        .into_iter()
+        .map(|ir| (ir.value.ir, ir.value.label))
+        .collect();
+    assert_eq!(code[0], (IR::Get { dst: 0, addr: 0 }, None));
+    assert_eq!(code[1], (IR::JumpIf { negative: true, cond: 0, addr: 10 }, None));
+    assert_eq!(code[3], (IR::JumpIf { negative: true, cond: 1, addr: 5 }, None));
+    assert_eq!(code[4], (IR::Jump { addr: 10 }, None));
+    assert_eq!(code[9], (IR::Jump { addr: 0 }, None));
+    assert_eq!(code[10], (IR::Return { src: None }, None));
+    assert!(code.iter().all(|(ir, label)| *ir != IR::None && label.is_none()));
+    let code: Vec<IR> = closure.closures[0].code.iter().map(|ir| ir.value.ir.clone()).collect();
+    assert_eq!(code[1], IR::JumpIf { negative: true, cond: 0, addr: 3 });
+    assert_eq!(code[2], IR::Jump { addr: 0 });
+
+    let mut closure = Closure::default();
+    closure.code.push(Located::new(LabeledIR::new(IR::Jump { addr: 7 }), Position::new(0..0, 1..2)));
+    assert_eq!(
+        closure.resolve_labels(),
+        Err(Located::new(LinkError::UndefinedLabel(7), Position::new(0..0, 1..2)))
+    );
+}
+
+#[test]
+fn register_allocation() {
+    let mut compiler = IRCompiler::new();
+    assert_eq!(compiler.alloc_registers(3), 0);
+    compiler.free_register(1);
+    assert_eq!(compiler.alloc_register(), 1);
+    compiler.free_register(1);
+    assert_eq!(compiler.alloc_registers(2), 3);
+    let temp = compiler.with_temp(|compiler, temp| {
+        assert_eq!(compiler.alloc_register(), 5);
+        temp
+    });
+    assert_eq!(temp, 1);
+    assert_eq!(compiler.alloc_register(), 1);
+    assert_eq!(compiler.pop_closure().unwrap().registers, 6);
+
+    let closure = compile("f(1, 2); f(3); fn g(a) { h(a, a); }").unwrap();
+    assert_eq!(closure.registers, 5);
+    assert_eq!(closure.code[6].value.ir, IR::Get { dst: 0, addr: 0 });
+    assert_eq!(closure.closures[0].registers, 4);
+}
+
+#[test]
+fn constant_interning() {
+    let closure = compile("print(\"a\"); print(\"a\");").unwrap();
+    assert_eq!(closure.string, vec!["print", "a"]);
+    let closure = compile("f(1, 1.5, 1, 1.5, 0.0); g(fn() { f(1); });").unwrap();
+    assert_eq!(closure.int, vec![1]);
+    assert_eq!(closure.float, vec![1.5, 0.0]);
+    assert_eq!(closure.string, vec!["f", "g"]);
+    assert_eq!(closure.closures[0].string, vec!["f"]);
+    assert_eq!(closure.closures[0].int, vec![1]);
+}
+
+#[test]
+fn peephole_optimization() {
+    let at = |ir: IR| Located::new(LabeledIR::new(ir), Position::default());
+    let label = |label| Located::new(LabeledIR::new(IR::None).labeled(label), Position::default());
+    let mut closure = Closure {
+        code: vec![
+            at(IR::Int { dst: 0, addr: 0 }),
+            at(IR::Move { dst: 0, src: 0 }),
+            at(IR::Int { dst: 1, addr: 0 }),
+            at(IR::Move { dst: 2, src: 1 }),
+            at(IR::JumpIf { negative: true, cond: 0, addr: 0 }),
+            label(0),
+            label(1),
+            at(IR::Jump { addr: 2 }),
+            label(2),
+            at(IR::Jump { addr: 3 }),
+            label(3),
+            at(IR::Return { src: None }),
+        ],
+        ..Default::default()
+    };
+    optimize::peephole(&mut closure);
+    let code: Vec<IR> = closure.code.into_iter().map(|ir| ir.value.ir).collect();
+    assert_eq!(
+        code,
+        vec![
+            IR::Int { dst: 0, addr: 0 },
+            IR::JumpIf { negative: true, cond: 0, addr: 3 },
+            IR::None,
+            IR::None,
+            IR::Jump { addr: 3 },
+            IR::None,
+            IR::Jump { addr: 3 },
+            IR::None,
+            IR::Return { src: None },
+        ]
+    );
+
+    let ast = program("fn f(a, b) { b = a; b = b; return [a, b]; } return f(2);");
+    let closure = compiler::compile(&ast, OptLevel::Peephole).unwrap();
+    let unoptimized = compiler::compile(&ast, OptLevel::None).unwrap();
+    assert!(closure.closures[0].code.len() < unoptimized.closures[0].code.len());
+    assert!(closure.closures[0].code.iter().all(|ir| !matches!(ir.value.ir, IR::Move { dst, src } if dst == src)));
+    assert_eq!(closure, compiler::compile(&ast, OptLevel::Peephole).unwrap());
+    assert_eq!(run("fn f(a, b) { b = a; b = b; return [a, b]; } return f(2);"), run("return [2, 2];"));
+
+    // unread writes that can fail are kept, so every level raises the same errors
+    let run_at = |text, level| {
+        let mut closure = compiler::compile(&program(text), level).unwrap();
+        closure.resolve_labels().unwrap();
+        Interpreter::new().with_limits(Limits { max_allocations: 2, ..Limits::default() }).run(&Arc::new(closure)).map_err(Located::unwrap)
+    };
+    for text in ["f = fn() { x = undefinedthing; return 1; }; return f();", "f = fn() { x = [1, 2]; return 1; }; return f();"] {
+        assert!(run_at(text, OptLevel::None).is_err());
+        assert_eq!(run_at(text, OptLevel::None), run_at(text, OptLevel::Full));
+    }
+}
+
+#[test]
+fn constant_folding() {
+    let fold = |text| {
+        let mut ast = program(text);
+        parser::fold(&mut ast.value);
+        ast
+    };
+    let expr = |ast: &Located<Program>| match &ast.value.0[0].value {
+        Statement::Assign { path: _, expr } => expr.value.clone(),
+        stat => panic!("expected assignment, got {stat:?}"),
+    };
+    assert_eq!(expr(&fold("a = 1 + 2 * 3;")), Expression::Atom(Atom::Integer(7)));
+    assert_eq!(expr(&fold("a = (1 + 2) * 3;")), Expression::Atom(Atom::Integer(9)));
+    assert_eq!(expr(&fold("a = 1 + 0.5;")), Expression::Atom(Atom::Decimal(1.5)));
+    assert_eq!(expr(&fold("a = \"a\" + \"b\";")), Expression::Atom(Atom::String("ab".to_string())));
+    assert_eq!(expr(&fold("a = -(2 - 5) < 4;")), Expression::Atom(Atom::Bool(true)));
+    assert_eq!(expr(&fold("a = !(null == null);")), Expression::Atom(Atom::Bool(false)));
+    assert!(matches!(expr(&fold("a = 1 / 0;")), Expression::Binary { .. }));
+    assert!(matches!(expr(&fold("a = 1 + \"b\";")), Expression::Binary { .. }));
+    assert!(matches!(expr(&fold("a = b + 1 * 2;")), Expression::Binary { .. }));
+
+    let closure = compiler::compile(&fold("print(2 * 3 + 4, \"x\" + \"y\");"), OptLevel::None).unwrap();
+    assert_eq!(closure.int, vec![10]);
+    assert_eq!(closure.string, vec!["print", "xy"]);
+}
+
+#[test]
+fn unreachable_code_removal() {
+    let at = |ir: IR| Located::new(LabeledIR::new(ir), Position::default());
+    let label = |label| Located::new(LabeledIR::new(IR::None).labeled(label), Position::default());
+    let mut closure = Closure {
+        code: vec![
+            at(IR::Bool { dst: 0, value: true }),
+            at(IR::JumpIf { negative: true, cond: 0, addr: 0 }),
+            at(IR::Jump { addr: 1 }),
+            at(IR::Int { dst: 1, addr: 0 }),
+            label(0),
+            at(IR::Return { src: Some(0) }),
+            at(IR::Null { dst: 1 }),
+            label(1),
+            at(IR::Return { src: None }),
+        ],
+        ..Default::default()
+    };
+    let cfg = Cfg::new(&closure);
+    let blocks: Vec<_> = cfg.blocks.iter().map(|block| (block.code.clone(), block.successors.clone())).collect();
+    assert_eq!(
+        blocks,
+        vec![
+            (0..2, vec![1, 3]),
+            (2..3, vec![5]),
+            (3..4, vec![3]),
+            (4..6, vec![]),
+            (6..7, vec![5]),
+            (7..9, vec![]),
+        ]
+    );
+    assert_eq!(cfg.reachable(), vec![true, true, false, true, false, true]);
+    cfg::remove_unreachable(&mut closure);
+    closure.resolve_labels().unwrap();
+    let code: Vec<IR> = closure.code.into_iter().map(|ir| ir.value.ir).collect();
+    assert_eq!(
+        code,
+        vec![
+            IR::Bool { dst: 0, value: true },
+            IR::JumpIf { negative: true, cond: 0, addr: 3 },
+            IR::Jump { addr: 4 },
+            IR::Return { src: Some(0) },
+            IR::Return { src: None },
+        ]
+    );
+
+    let mut closure = compiler::compile(&program("fn f(a) { return a; g(a); } while true { break; h(); }"), OptLevel::Full).unwrap();
+    assert!(closure.code.iter().all(|ir| !matches!(ir.value.ir, IR::Call { .. })));
+    assert!(closure.closures[0].code.iter().all(|ir| !matches!(ir.value.ir, IR::Call { .. })));
+    closure.resolve_labels().unwrap();
+}
+
+#[test]
+fn compiling_operators() {
+    let closure = compile("a = b * -c + 1;").unwrap();
+    let code: Vec<IR> = closure.code.into_iter().map(|ir| ir.value.ir).collect();
+    assert_eq!(
+        code,
+        vec![
+            IR::Get { dst: 0, addr: 0 },
+            IR::Get { dst: 1, addr: 1 },
+            IR::Unary { op: UnaryOperator::Neg, dst: 2, src: 1 },
+            IR::Binary { op: BinaryOperator::Mul, dst: 3, left: 0, right: 2 },
+            IR::Int { dst: 4, addr: 0 },
+            IR::Binary { op: BinaryOperator::Add, dst: 5, left: 3, right: 4 },
+            IR::Set { addr: 2, src: 5 },
+            IR::Return { src: None },
+        ]
+    );
+    assert_eq!(closure.string, vec!["b", "c", "a"]);
+}
+
+/// Compiles `text` without optimizing or resolving labels
+fn compile(text: &str) -> Result<Closure, Located<CompileError>> {
+    compiler::compile(&program(text), OptLevel::None)
+}
+/// Compiles `text` with all optimizations and runs it on `interpreter`
+fn run_with(interpreter: &mut Interpreter, text: &str) -> Result<Value, RuntimeError> {
+    let mut closure = compiler::compile(&program(text), OptLevel::Full).unwrap();
+    closure.resolve_labels().unwrap();
+    interpreter.run(&Arc::new(closure)).map_err(Located::unwrap)
+}
+fn run(text: &str) -> Result<Value, RuntimeError> {
+    run_with(&mut Interpreter::new(), text)
+}
+
+#[test]
+fn running_programs() {
+    assert_eq!(run("fn fib(n) { if n < 2 { return n; } return fib(n - 1) + fib(n - 2); } return fib(10);"), Ok(Value::Int(55)));
+    assert_eq!(run("i = 0; while i < 10 { i = i + 1; } return i;"), Ok(Value::Int(10)));
+    assert_eq!(
+        run("fn fib(n) { if n < 2 { return n; } a = fib(n - 1); b = fib(n - 2); return a + b; } return fib(10);"),
+        Ok(Value::Int(55))
+    );
+    assert_eq!(run("fn f(x) { b = x + 1; } f(1); return b;"), Err(RuntimeError::UndefinedGlobal("b".to_string())));
+    assert_eq!(run("n = 1; fn f() { n = 2; return n; } return [f(), n];"), run("return [2, 1];"));
+    assert_eq!(run("fn f(l) { s = 0; for x in l { t = x * 2; s = s + t; } return s; } return f([1, 2, 3]);"), Ok(Value::Int(12)));
+    assert_eq!(
+        run("s = 0; for x in [1, 2, 3, 4, 5] { if x == 2 { continue; } if x == 5 { break; } s = s + x; } return s;"),
+        Ok(Value::Int(8))
+    );
+    assert_eq!(run("m = { a = 1; }; m.b = 2.5; m[\"c\"] = m.a + m.b; return m.c;"), Ok(Value::Float(3.5)));
+    assert_eq!(run("l = [1, [2, 3]]; l[1][0] = -l[0]; return l;"), Ok(Value::from(vec![Value::Int(1), Value::from(vec![Value::Int(-1), Value::Int(3)])])));
+    assert_eq!(
+        run("fn f(a, b, kw) { return [a, b, kw.x]; } args = [1]; return [f(1, 2, x = 3), f(...args, 2, x = 3)];"),
+        run("return [[1, 2, 3], [1, 2, 3]];")
+    );
+    assert_eq!(run("return \"a\" + \"b\" < \"b\";"), Ok(Value::Bool(true)));
+    assert_eq!(run("return !null == (1 == 1.0);"), Ok(Value::Bool(true)));
+    assert_eq!(run("add = fn(a, b) { return a + b; }; return add(\"a\", \"b\");"), Ok(Value::String("ab".to_string())));
+    assert_eq!(run("print(1);"), Err(RuntimeError::UndefinedGlobal("print".to_string())));
+    assert_eq!(run("return 1 / 0;"), Err(RuntimeError::DivisionByZero));
+    assert_eq!(run("return 1 + \"a\";"), Err(RuntimeError::InvalidBinary(BinaryOperator::Add, "int", "string")));
+    assert_eq!(run("return [1][1];"), Err(RuntimeError::IndexOutOfRange(1, 1)));
+    assert_eq!(run("x = 1; x();"), Err(RuntimeError::NotCallable("int")));
+    assert_eq!(run("fn f(a) { return a; } return f();"), Ok(Value::Null));
+    assert_eq!(run("fn f(a) { b = 0; return b; } return f(1, 2);"), Err(RuntimeError::ArgumentCount(1, 2)));
+    assert_eq!(run("fn f(a) { return a; } return f(1, k = 2);"), Err(RuntimeError::ArgumentCount(1, 2)));
+
+    let mut interpreter = Interpreter::new();
+    assert_eq!(run_with(&mut interpreter, "a = 1; b = fn() { return a + 1; };"), Ok(Value::Null));
+    assert_eq!(interpreter.globals.get("a"), Some(&Value::Int(1)));
+    assert!(matches!(interpreter.globals.get("b"), Some(Value::Function(_))));
+}
+
+#[test]
+fn native_functions() {
+    let output = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+    let mut interpreter = Interpreter::new();
+    let printed = output.clone();
+    interpreter.register("print", move |args| {
+        printed.borrow_mut().push(args.to_vec());
+        Ok(Value::Null)
+    });
+    interpreter.register("double", |args| match args {
+        [Value::Int(int)] => Ok(Value::Int(int * 2)),
+        _ => Err(RuntimeError::Custom("expected an int".to_string())),
+    });
+    assert_eq!(run_with(&mut interpreter, "print(double(21), end = \"\");"), Ok(Value::Null));
+    let mut end = std::collections::BTreeMap::new();
+    end.insert("end".to_string(), Value::String(String::new()));
+    assert_eq!(*output.borrow(), vec![vec![Value::Int(42), Value::from(end)]]);
+    assert_eq!(run_with(&mut interpreter, "return double(\"a\");"), Err(RuntimeError::Custom("expected an int".to_string())));
+    assert!(matches!(interpreter.globals.get("print"), Some(Value::Native(native)) if native.name == "print"));
+}
+
+#[test]
+fn runtime_error_positions() {
+    let closure = Arc::new(crate::compile("fn f(x) {\n    return x.y + 1;\n}\nf({ y = \"a\"; });").unwrap());
+    let err = Interpreter::new().run(&closure).unwrap_err();
+    assert_eq!(err.value, RuntimeError::InvalidBinary(BinaryOperator::Add, "string", "int"));
+    assert_eq!(err.pos, Position::new(1..1, 11..18));
+    assert_eq!(err.to_string(), "2:12: cannot apply `+` to string and int");
+    assert_eq!(closure.position(0), Some(&closure.code[0].pos));
+    assert_eq!(closure.position(closure.code.len()), None);
+}
+
+/// Output of an interpreter, shared with the test reading it
+#[derive(Clone, Default)]
+struct Buffer(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+impl std::io::Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+impl Buffer {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+#[test]
+fn standard_library() {
+    let run = |text| run_with(&mut Interpreter::with_stdlib(), text);
+    let string = |string: &str| Ok(Value::String(string.to_string()));
+    assert_eq!(run("l = [1]; push(l, \"a\"); return str(l);"), string("[1, \"a\"]"));
+    assert_eq!(run("return [len(\"héllo\"), len([1, 2]), len({ a = 1; })];"), run("return [5, 2, 1];"));
+    assert_eq!(run("return str(keys({ b = 1; a = { c = null; }; }));"), string("[\"a\", \"b\"]"));
+    assert_eq!(run("return str({ a = { c = null; }; b = {}; });"), string("{ \"a\" = { \"c\" = null; }; \"b\" = {}; }"));
+    assert_eq!(run("return [type(1), type(1.5), type(print), type(fn() {})];"), run("return [\"int\", \"float\", \"function\", \"function\"];"));
+    assert_eq!(run("return [int(\" 12 \"), int(-2.7), int(true), float(\"0.5\"), float(2)];"), run("return [12, -2, 1, 0.5, 2.0];"));
+    assert_eq!(run("return [abs(-3), abs(-1.5), min(3, 1.5, 2), max(1, 4, 2), floor(1.5), ceil(1.5), round(2), sqrt(4)];"), run("return [3, 1.5, 1.5, 4, 1.0, 2.0, 2, 2.0];"));
+    assert_eq!(run("return [pow(2, 10), pow(2, -1), pow(4, 0.5)];"), run("return [1024, 0.5, 2.0];"));
+    assert_eq!(run("return str(1.0) + str(true) + str(null);"), string("1.0truenull"));
+    assert_eq!(run("return len(1);"), Err(RuntimeError::InvalidArgument(0, "int")));
+    assert_eq!(run("return len();"), Err(RuntimeError::ArgumentCount(1, 0)));
+    assert_eq!(run("return min();"), Err(RuntimeError::ArgumentCount(1, 0)));
+    assert_eq!(run("return pow(2, 64);"), Err(RuntimeError::Overflow));
+    assert_eq!(run("return int(\"x\");"), Err(RuntimeError::Custom("cannot convert \"x\" to an int".to_string())));
+    assert_eq!(Interpreter::new().globals.get("print"), None);
+
+    let output = Buffer::default();
+    let mut interpreter = Interpreter::with_stdlib().with_output(output.clone());
+    assert_eq!(run_with(&mut interpreter, "print(\"hello\", 1, [\"a\"]); print();"), Ok(Value::Null));
+    assert_eq!(output.text(), "hello 1 [\"a\"]\n\n");
+}
+
+#[test]
+fn cyclic_values() {
+    let output = Buffer::default();
+    let run = |text: &str| run_with(&mut Interpreter::with_stdlib().with_output(output.clone()), &format!("a = []; push(a, a); b = []; push(b, b); m = {{}}; m.self = m; m.list = a; {text}"));
+    let string = |string: &str| Ok(Value::String(string.to_string()));
+    assert_eq!(run("return a == b;"), Ok(Value::Bool(true)));
+    assert_eq!(run("push(b, 1); return a == b;"), Ok(Value::Bool(false)));
+    assert_eq!(run("return [1 == 1.0, [1] == [1.0], { x = [2]; } == { x = [2.0]; }, [1] != [1.5]];"), run("return [true, true, true, true];"));
+    assert_ne!(Value::from(vec![Value::Int(1)]), Value::from(vec![Value::Float(1.0)]));
+    assert_eq!(run("return str(a);"), string("[[...]]"));
+    assert_eq!(run("return str(m);"), string("{ \"list\" = [[...]]; \"self\" = {...}; }"));
+    assert_eq!(run("print(a, m); return m == m.self;"), Ok(Value::Bool(true)));
+    assert_eq!(output.text(), "[[...]] { \"list\" = [[...]]; \"self\" = {...}; }\n");
+    assert_eq!(format!("{:?}", run("return [1.0, a];").unwrap()), "List([1.0, [[...]]])");
+}
+
+#[test]
+fn execution_limits() {
+    let limits = Limits {
+        max_instructions: 1000,
+        max_call_depth: 2,
+        max_allocations: 8,
+        max_string_len: 16,
+    };
+    let mut interpreter = Interpreter::with_stdlib().with_limits(limits);
+    let mut run = |text| run_with(&mut interpreter, text);
+    assert_eq!(run("while true {}"), Err(RuntimeError::LimitExceeded(Limit::Instructions)));
+    assert_eq!(run("i = 0; while i < 100 { i = i + 1; } return i;"), Ok(Value::Int(100)));
+    assert_eq!(run("fn f() { return g(); } fn g() { return 1; } return f();"), Ok(Value::Int(1)));
+    assert_eq!(run("fn f(n) { return f(n + 1); } f(0);"), Err(RuntimeError::LimitExceeded(Limit::CallDepth)));
+    assert!(run("return [[1], {}];").is_ok());
+    assert_eq!(run("return [1, 2, 3, 4, 5, 6, 7, 8];"), Err(RuntimeError::LimitExceeded(Limit::Allocations)));
+    assert_eq!(run("a = [1]; while true { a = [...a, ...a]; }"), Err(RuntimeError::LimitExceeded(Limit::Allocations)));
+    assert_eq!(run("m = {}; i = 0; while true { m[str(i)] = i; i = i + 1; }"), Err(RuntimeError::LimitExceeded(Limit::Allocations)));
+    assert_eq!(run("l = []; while true { push(l, l); }"), Err(RuntimeError::LimitExceeded(Limit::Allocations)));
+    assert_eq!(run("s = \"ab\"; while true { s = s + s; }"), Err(RuntimeError::LimitExceeded(Limit::StringLength)));
+    assert_eq!(run("return str([1, 2, 3, 4, 5, 6]);"), Err(RuntimeError::LimitExceeded(Limit::StringLength)));
+    assert_eq!(Limits::default().max_instructions, usize::MAX);
+}
+
+#[test]
+fn source_pipeline() {
+    let program = crate::parse("a = 1;\nb = a + 2;").unwrap();
+    assert_eq!(program.value.0.len(), 2);
+    assert_eq!(program.pos.ln, 0..1);
+
+    let closure = Arc::new(crate::compile("x = 2 * 3; return x + 1;").unwrap());
+    assert_eq!(closure.int, vec![6, 1]);
+    assert!(closure.code.iter().all(|ir| ir.value.label.is_none()));
+    assert_eq!(Interpreter::new().run(&closure).map_err(Located::unwrap), Ok(Value::Int(7)));
+
+    assert!(matches!(crate::parse("a = \"b;"), Err(Error::Lex(err)) if err.value == LexError::UnclosedString));
+    assert!(matches!(crate::compile("a = ;"), Err(Error::Parse(_))));
+    let Err(err) = crate::compile("a = 1;\ncontinue;") else {
+        panic!("expected an error");
+    };
+    assert_eq!(err, Error::Compile(Located::new(CompileError::ContinueOutsideLoop, Position::default())));
+    assert_eq!(err.pos().ln, 1..1);
+    assert!(matches!(crate::compile("break;"), Err(Error::Compile(err)) if err.value == CompileError::BreakOutsideLoop));
+}
+
+#[test]
+fn error_messages() {
+    use std::error::Error as _;
+    let message = |src| crate::compile(src).unwrap_err().to_string();
+    assert_eq!(message("a = 1 $ 2;"), "1:7: bad character '$'");
+    assert_eq!(message("a = 99999999999999999999;"), "1:5: invalid integer: number too large to fit in target type");
+    assert_eq!(message("a = ;"), "1:5: unexpected `;`");
+    assert_eq!(message("f(a, b c);"), "1:8: expected `,` before identifier `c`");
+    assert_eq!(message("fn f() {\n  break;\n}"), "2:3: `break` outside of a loop");
+    assert_eq!(message("fn f(a) {\n  g(fn() { return a; });\n}"), "2:19: cannot use `a` here, it is a local of an enclosing function");
+    let err = crate::compile("a = 99999999999999999999;").unwrap_err();
+    assert!(err.source().is_some());
+    assert!(crate::compile("a = ;").unwrap_err().source().is_none());
+    let expected = ParseError::ExpectedTokens { expected: &[Token::Semicolon, Token::BraceRight], got: Token::String("b".to_string()) };
+    assert_eq!(expected.to_string(), "expected one of `;`, `}`, got literal \"b\"");
+    assert_eq!(ParseError::ExpectedToken { expected: Token::Ident(String::new()), got: Token::Ellipsis }.to_string(), "expected identifier, got `...`");
+    assert_eq!(LinkError::UndefinedLabel(3).to_string(), "jump to undefined label 3");
+    assert_eq!(RuntimeError::LimitExceeded(Limit::CallDepth).to_string(), "call depth limit exceeded");
+    let boxed: Box<dyn std::error::Error> = Box::new(Located::new(CompileError::MisplacedSpread, Position::new(0..0, 4..8)));
+    assert_eq!(boxed.to_string(), "1:5: `...` is only allowed in lists and arguments");
+}
+
+#[test]
+#[cfg(not(feature = "color"))]
+fn diagnostic_rendering() {
+    let src = "fn f(x) {\n    return x.y + 1;\n}\nf({ y = \"a\"; });";
+    let closure = Arc::new(crate::compile(src).unwrap());
+    let err = Interpreter::new().run(&closure).unwrap_err();
+    assert_eq!(
+        crate::diagnostics::render(&err, src),
+        "error: cannot apply `+` to string and int\n --> 2:12\n  |\n2 |     return x.y + 1;\n  |            ^^^^^^^\n"
+    );
+    let src = "a = 1;\nb = [1,\n  2 3];";
+    let err = crate::parse(src).unwrap_err();
+    assert_eq!(
+        crate::diagnostics::render_error(&err, src),
+        "error: expected `,` before literal 3\n --> 3:5\n  |\n3 |   2 3];\n  |     ^\n"
+    );
+    let err = Located::new(ParseError::UnexpectedEOF, Position::new(9..10, 0..1));
+    assert_eq!(
+        crate::diagnostics::render(&err, "x\n".repeat(11).as_str()),
+        "error: unexpected end of input\n  --> 10:1\n   |\n10 | x\n   | ^\n11 | x\n   | ^\n"
+    );
+}
@@ -0,0 +1,225 @@
+//! Compact binary encoding of lexed tokens, for handing a token stream to another process
+//!
+//! Every token is written as its position (four LEB128 integers: `ln.start`, `ln.end`,
+//! `col.start`, `col.end`), a tag byte and the tag's payload.
+
+use crate::{
+    lexer::Token,
+    position::{Located, Position},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    UnexpectedEnd,
+    BadTag(u8),
+    BadUtf8,
+    IntegerOverflow,
+}
+
+const IDENT: u8 = 0;
+const INTEGER: u8 = 1;
+const DECIMAL: u8 = 2;
+const STRING: u8 = 3;
+const PARAN_LEFT: u8 = 4;
+const PARAN_RIGHT: u8 = 5;
+const BRACKET_LEFT: u8 = 6;
+const BRACKET_RIGHT: u8 = 7;
+const BRACE_LEFT: u8 = 8;
+const BRACE_RIGHT: u8 = 9;
+const EQUAL: u8 = 10;
+const SEMICOLON: u8 = 11;
+const DOT: u8 = 12;
+const PIPE: u8 = 13;
+const COMMA: u8 = 14;
+const PLUS: u8 = 15;
+const MINUS: u8 = 16;
+const STAR: u8 = 17;
+const SLASH: u8 = 18;
+const PERCENT: u8 = 19;
+const EQUAL_EQUAL: u8 = 20;
+const EXCLAMATION_EQUAL: u8 = 21;
+const LESS: u8 = 22;
+const LESS_EQUAL: u8 = 23;
+const GREATER: u8 = 24;
+const GREATER_EQUAL: u8 = 25;
+const EXCLAMATION: u8 = 26;
+const BOOL: u8 = 27;
+const NULL: u8 = 28;
+const IF: u8 = 29;
+const ELSE: u8 = 30;
+const WHILE: u8 = 31;
+const FN: u8 = 32;
+const FOR: u8 = 33;
+const IN: u8 = 34;
+const BREAK: u8 = 35;
+const CONTINUE: u8 = 36;
+const RETURN: u8 = 37;
+const ELLIPSIS: u8 = 38;
+
+pub fn encode(tokens: &[Located<Token>]) -> Vec<u8> {
+    let mut bytes = vec![];
+    for Located { value: token, pos } in tokens {
+        for n in [pos.ln.start, pos.ln.end, pos.col.start, pos.col.end] {
+            write_usize(&mut bytes, n);
+        }
+        match token {
+            Token::Ident(ident) => {
+                bytes.push(IDENT);
+                write_str(&mut bytes, ident);
+            }
+            Token::Integer(value) => {
+                bytes.push(INTEGER);
+                bytes.extend(value.to_le_bytes());
+            }
+            Token::Decimal(value) => {
+                bytes.push(DECIMAL);
+                bytes.extend(value.to_le_bytes());
+            }
+            Token::String(string) => {
+                bytes.push(STRING);
+                write_str(&mut bytes, string);
+            }
+            Token::Bool(value) => {
+                bytes.push(BOOL);
+                bytes.push(*value as u8);
+            }
+            Token::Null => bytes.push(NULL),
+            Token::ParanLeft => bytes.push(PARAN_LEFT),
+            Token::ParanRight => bytes.push(PARAN_RIGHT),
+            Token::BracketLeft => bytes.push(BRACKET_LEFT),
+            Token::BracketRight => bytes.push(BRACKET_RIGHT),
+            Token::BraceLeft => bytes.push(BRACE_LEFT),
+            Token::BraceRight => bytes.push(BRACE_RIGHT),
+            Token::Equal => bytes.push(EQUAL),
+            Token::Semicolon => bytes.push(SEMICOLON),
+            Token::Dot => bytes.push(DOT),
+            Token::Pipe => bytes.push(PIPE),
+            Token::Comma => bytes.push(COMMA),
+            Token::Plus => bytes.push(PLUS),
+            Token::Minus => bytes.push(MINUS),
+            Token::Star => bytes.push(STAR),
+            Token::Slash => bytes.push(SLASH),
+            Token::Percent => bytes.push(PERCENT),
+            Token::EqualEqual => bytes.push(EQUAL_EQUAL),
+            Token::ExclamationEqual => bytes.push(EXCLAMATION_EQUAL),
+            Token::Less => bytes.push(LESS),
+            Token::LessEqual => bytes.push(LESS_EQUAL),
+            Token::Greater => bytes.push(GREATER),
+            Token::GreaterEqual => bytes.push(GREATER_EQUAL),
+            Token::Exclamation => bytes.push(EXCLAMATION),
+            Token::If => bytes.push(IF),
+            Token::Else => bytes.push(ELSE),
+            Token::While => bytes.push(WHILE),
+            Token::Fn => bytes.push(FN),
+            Token::For => bytes.push(FOR),
+            Token::In => bytes.push(IN),
+            Token::Break => bytes.push(BREAK),
+            Token::Continue => bytes.push(CONTINUE),
+            Token::Return => bytes.push(RETURN),
+            Token::Ellipsis => bytes.push(ELLIPSIS),
+        }
+    }
+    bytes
+}
+pub fn decode(mut bytes: &[u8]) -> Result<Vec<Located<Token>>, DecodeError> {
+    let mut tokens = vec![];
+    while !bytes.is_empty() {
+        let ln = read_usize(&mut bytes)?..read_usize(&mut bytes)?;
+        let col = read_usize(&mut bytes)?..read_usize(&mut bytes)?;
+        let token = match read_byte(&mut bytes)? {
+            IDENT => Token::Ident(read_str(&mut bytes)?),
+            INTEGER => Token::Integer(i64::from_le_bytes(read_array(&mut bytes)?)),
+            DECIMAL => Token::Decimal(f64::from_le_bytes(read_array(&mut bytes)?)),
+            STRING => Token::String(read_str(&mut bytes)?),
+            BOOL => Token::Bool(read_byte(&mut bytes)? != 0),
+            NULL => Token::Null,
+            PARAN_LEFT => Token::ParanLeft,
+            PARAN_RIGHT => Token::ParanRight,
+            BRACKET_LEFT => Token::BracketLeft,
+            BRACKET_RIGHT => Token::BracketRight,
+            BRACE_LEFT => Token::BraceLeft,
+            BRACE_RIGHT => Token::BraceRight,
+            EQUAL => Token::Equal,
+            SEMICOLON => Token::Semicolon,
+            DOT => Token::Dot,
+            PIPE => Token::Pipe,
+            COMMA => Token::Comma,
+            PLUS => Token::Plus,
+            MINUS => Token::Minus,
+            STAR => Token::Star,
+            SLASH => Token::Slash,
+            PERCENT => Token::Percent,
+            EQUAL_EQUAL => Token::EqualEqual,
+            EXCLAMATION_EQUAL => Token::ExclamationEqual,
+            LESS => Token::Less,
+            LESS_EQUAL => Token::LessEqual,
+            GREATER => Token::Greater,
+            GREATER_EQUAL => Token::GreaterEqual,
+            EXCLAMATION => Token::Exclamation,
+            IF => Token::If,
+            ELSE => Token::Else,
+            WHILE => Token::While,
+            FN => Token::Fn,
+            FOR => Token::For,
+            IN => Token::In,
+            BREAK => Token::Break,
+            CONTINUE => Token::Continue,
+            RETURN => Token::Return,
+            ELLIPSIS => Token::Ellipsis,
+            tag => return Err(DecodeError::BadTag(tag)),
+        };
+        tokens.push(Located::new(token, Position::new(ln, col)));
+    }
+    Ok(tokens)
+}
+
+fn write_usize(bytes: &mut Vec<u8>, mut n: usize) {
+    while n >= 0x80 {
+        bytes.push(n as u8 | 0x80);
+        n >>= 7;
+    }
+    bytes.push(n as u8);
+}
+fn write_str(bytes: &mut Vec<u8>, string: &str) {
+    write_usize(bytes, string.len());
+    bytes.extend(string.as_bytes());
+}
+fn read_byte(bytes: &mut &[u8]) -> Result<u8, DecodeError> {
+    let (byte, rest) = bytes.split_first().ok_or(DecodeError::UnexpectedEnd)?;
+    *bytes = rest;
+    Ok(*byte)
+}
+fn read_usize(bytes: &mut &[u8]) -> Result<usize, DecodeError> {
+    let mut n: usize = 0;
+    let mut shift = 0;
+    loop {
+        let byte = read_byte(bytes)?;
+        if shift >= usize::BITS {
+            return Err(DecodeError::IntegerOverflow);
+        }
+        n |= ((byte & 0x7f) as usize)
+            .checked_shl(shift)
+            .ok_or(DecodeError::IntegerOverflow)?;
+        if byte & 0x80 == 0 {
+            return Ok(n);
+        }
+        shift += 7;
+    }
+}
+fn read_array<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N], DecodeError> {
+    if bytes.len() < N {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+    let (array, rest) = bytes.split_at(N);
+    *bytes = rest;
+    Ok(array.try_into().unwrap())
+}
+fn read_str(bytes: &mut &[u8]) -> Result<String, DecodeError> {
+    let len = read_usize(bytes)?;
+    if bytes.len() < len {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+    let (string, rest) = bytes.split_at(len);
+    *bytes = rest;
+    String::from_utf8(string.to_vec()).map_err(|_| DecodeError::BadUtf8)
+}